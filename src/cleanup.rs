@@ -0,0 +1,123 @@
+use anyhow::bail;
+use once_cell::sync::OnceCell;
+use regex::Regex;
+
+pub(crate) static CLEANUP: OnceCell<Cleanup> = OnceCell::new();
+
+/// Built-in rules that can be used with `@name` instead of a regex
+static PRESETS: &[(&str, &str)] = &[
+    ("feat-tags", r" ?\((?:feat\.?|ft\.?|with) [^)]+\)"),
+    ("parentheses", r" ?\([^)]*\)"),
+    ("brackets", r" ?\[[^\]]*\]"),
+    (
+        "remaster",
+        r" ?[-(] ?(?:\d{4} )?Remaster(?:ed)?(?: \d{4})?\)?",
+    ),
+];
+
+/// Part of the track name a cleanup rule applies to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum CleanupField {
+    /// Whole file name without the extension
+    Name,
+    /// Track or episode title
+    Title,
+    /// Every artist name, or the show name for podcasts
+    Artist,
+}
+
+pub(crate) struct CleanupRule {
+    pub field: CleanupField,
+    pub regex: Regex,
+    pub replacement: String,
+}
+
+impl CleanupRule {
+    /// Parse a rule in the form `[field:]pattern[=>replacement]`. The pattern can be
+    /// a regex or `@preset`. Regex rules apply to the whole name by default and
+    /// presets apply to the title.
+    pub fn parse(rule: &str) -> anyhow::Result<Self> {
+        let (field, rest) = match rule.split_once(':') {
+            Some(("name", rest)) => (Some(CleanupField::Name), rest),
+            Some(("title", rest)) => (Some(CleanupField::Title), rest),
+            Some(("artist", rest)) => (Some(CleanupField::Artist), rest),
+            _ => (None, rule),
+        };
+
+        let (pattern, replacement) = match rest.rsplit_once("=>") {
+            Some((pattern, replacement)) => (pattern, replacement),
+            None => (rest, ""),
+        };
+
+        let (field, pattern) = match pattern.strip_prefix('@') {
+            Some(preset) => {
+                let Some((_, pattern)) = PRESETS.iter().find(|(name, _)| *name == preset) else {
+                    bail!(
+                        "Unknown cleanup preset: {}, expected one of {}",
+                        preset,
+                        PRESETS
+                            .iter()
+                            .map(|(name, _)| *name)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    );
+                };
+                (field.unwrap_or(CleanupField::Title), *pattern)
+            }
+            None => (field.unwrap_or(CleanupField::Name), pattern),
+        };
+
+        Ok(Self {
+            field,
+            regex: Regex::new(pattern)?,
+            replacement: replacement.to_owned(),
+        })
+    }
+}
+
+/// Ordered list of cleanup rules applied to file names and optionally tags
+#[derive(Default)]
+pub(crate) struct Cleanup {
+    pub rules: Vec<CleanupRule>,
+    /// Apply title and artist rules to embedded tags too
+    pub apply_to_tags: bool,
+}
+
+impl Cleanup {
+    pub fn new(rules: &[String], apply_to_tags: bool) -> anyhow::Result<Self> {
+        Ok(Self {
+            rules: rules
+                .iter()
+                .map(|rule| CleanupRule::parse(rule))
+                .collect::<anyhow::Result<Vec<_>>>()?,
+            apply_to_tags,
+        })
+    }
+
+    pub fn apply(&self, field: CleanupField, value: &str) -> String {
+        self.rules.iter().filter(|rule| rule.field == field).fold(
+            value.to_owned(),
+            |value, rule| {
+                rule.regex
+                    .replace_all(&value, rule.replacement.as_str())
+                    .into_owned()
+            },
+        )
+    }
+}
+
+/// Run cleanup rules for `field` if any were set
+pub(crate) fn clean(field: CleanupField, value: &str) -> String {
+    match CLEANUP.get() {
+        Some(cleanup) => cleanup.apply(field, value),
+        None => value.to_owned(),
+    }
+}
+
+/// Run cleanup rules for `field` on a tag value if tag cleanup is enabled
+pub(crate) fn clean_tag(field: CleanupField, value: &str) -> String {
+    match CLEANUP.get() {
+        Some(cleanup) if cleanup.apply_to_tags => cleanup.apply(field, value),
+        _ => value.to_owned(),
+    }
+}
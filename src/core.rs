@@ -1,13 +1,11 @@
 use crate::{
     DownloadArgs,
-    metadata::{
-        REGEX_FILTER, get_input_format, try_get_format_from_file_name, try_get_format_from_path,
-    },
+    metadata::{get_input_format, try_get_format_from_file_name, try_get_format_from_path},
 };
 use anyhow::{anyhow, bail};
-use itertools::Itertools;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::{
+    collections::HashSet,
     path::{Path, PathBuf},
     sync::{
         Arc,
@@ -19,8 +17,8 @@ use anyhow::Context;
 use librespot::{
     core::{Session, SpotifyId, spotify_id::SpotifyItemType},
     metadata::{
-        Album, Metadata, Playlist, Show,
-        audio::{AudioFileFormat, AudioItem, UniqueFields},
+        Album, Episode, Metadata, Playlist, Show,
+        audio::{AudioFileFormat, AudioItem},
     },
     playback::{
         audio_backend,
@@ -29,15 +27,24 @@ use librespot::{
         player::{Player, PlayerEvent},
     },
 };
-use regex::Regex;
 use tokio::{
-    fs::{File, create_dir_all},
+    fs::{File, create_dir_all, remove_file},
     process::{Child, Command},
 };
 
-use crate::metadata::get_file_name;
+use crate::{
+    export::{ExportEntry, write_playlists},
+    feed::write_feed,
+    loudness::{ReplayGain, ReplayGainMode},
+    lyrics::{LyricsMode, TrackLyrics},
+    metadata::get_file_name,
+    playlist::TrackOverrides,
+    podcast::{ShowNotesMode, write_ffmetadata, write_show_notes},
+    rename::list_audio_files,
+    tags::{Tags, read_uri, write_tags},
+};
 
-#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, Serialize, PartialEq)]
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "kebab-case")]
 pub enum OutputFormat {
     #[default]
@@ -45,6 +52,7 @@ pub enum OutputFormat {
     Wav,
     Ogg,
     Mp3,
+    M4a,
 }
 
 impl OutputFormat {
@@ -54,6 +62,17 @@ impl OutputFormat {
             OutputFormat::Mp3 => "mp3",
             OutputFormat::Ogg => "ogg",
             OutputFormat::Wav => "wav",
+            OutputFormat::M4a => "m4a",
+        }
+    }
+
+    pub fn mime_type(&self) -> &str {
+        match self {
+            OutputFormat::Opus => "audio/ogg; codecs=opus",
+            OutputFormat::Mp3 => "audio/mpeg",
+            OutputFormat::Ogg => "audio/ogg",
+            OutputFormat::Wav => "audio/wav",
+            OutputFormat::M4a => "audio/mp4",
         }
     }
 
@@ -63,12 +82,33 @@ impl OutputFormat {
             "mp3" => Some(OutputFormat::Mp3),
             "ogg" => Some(OutputFormat::Ogg),
             "wav" => Some(OutputFormat::Wav),
+            "m4a" => Some(OutputFormat::M4a),
             _ => None,
         }
     }
 }
 
-fn get_bitrate(format: &AudioFileFormat) -> u32 {
+/// Spotify URIs saved in the tags of files in a folder
+async fn downloaded_uris(folder: &Path) -> anyhow::Result<HashSet<String>> {
+    let mut uris = HashSet::new();
+    for path in list_audio_files(folder).await? {
+        match read_uri(&path) {
+            Ok(Some(uri)) => {
+                uris.insert(uri);
+            }
+            Ok(None) => {}
+            Err(e) => log::warn!("Failed to read tags in {}: {}", path.to_string_lossy(), e),
+        }
+    }
+    Ok(uris)
+}
+
+/// Sample rate of decoded audio
+pub(crate) const SAMPLE_RATE: u64 = 44100;
+/// Size of one stereo signed 16-bit sample
+const BYTES_PER_SAMPLE: u64 = 4;
+
+pub(crate) fn get_bitrate(format: &AudioFileFormat) -> u32 {
     match format {
         AudioFileFormat::OGG_VORBIS_96 => 96,
         AudioFileFormat::OGG_VORBIS_160 => 160,
@@ -92,21 +132,11 @@ fn get_bitrate(format: &AudioFileFormat) -> u32 {
     }
 }
 
-pub(crate) trait CommandExt {
-    fn with_metadata(&mut self, name: &str, value: &str) -> &mut Self;
-}
-
-impl CommandExt for Command {
-    fn with_metadata(&mut self, name: &str, value: &str) -> &mut Self {
-        self.arg("-metadata").arg(format!("{}={}", name, value))
-    }
-}
-
 fn get_ffmpeg_command(
     input_format: Option<AudioFileFormat>,
     output_format: OutputFormat,
     output_file_name: &Path,
-    audio_item: &AudioItem,
+    chapters_file: Option<&Path>,
 ) -> Result<Child, std::io::Error> {
     // Read track as stereo signed 16-bit PCM and encode into audio file
     const COMMON_ARGS: &[&str] = &[
@@ -123,33 +153,19 @@ fn get_ffmpeg_command(
     ];
 
     let mut cmd = Command::new("ffmpeg");
-    let cmd = cmd
-        .args(COMMON_ARGS)
-        .with_metadata("title", &audio_item.name)
-        .with_metadata("comment", &audio_item.uri);
-
-    let cmd = match &audio_item.unique_fields {
-        UniqueFields::Episode {
-            show_name,
-            description,
-            ..
-        } => cmd
-            .with_metadata("show", &show_name)
-            .with_metadata("description", &description),
-        UniqueFields::Track {
-            artists,
-            album,
-            album_artists,
-            number,
-            ..
-        } => cmd
-            .with_metadata(
-                "artist",
-                &artists.iter().map(|artist| &*artist.name).join(", "),
-            )
-            .with_metadata("album", &album)
-            .with_metadata("album_artist", &album_artists.iter().join(", "))
-            .with_metadata("track", &number.to_string()),
+    let cmd = cmd.args(COMMON_ARGS);
+
+    if let Some(chapters_file) = chapters_file {
+        cmd.args(["-f", "ffmetadata", "-i"])
+            .arg(chapters_file)
+            .args(["-map", "0:a", "-map_chapters", "1"]);
+    }
+
+    // Both encoders write encoder delay and padding so players can skip them
+    let cmd = match output_format {
+        OutputFormat::Mp3 => cmd.args(["-c:a", "libmp3lame", "-write_xing", "1"]),
+        OutputFormat::M4a => cmd.args(["-c:a", "aac"]),
+        _ => cmd,
     };
 
     if output_format == OutputFormat::Wav || input_format.is_none() {
@@ -165,6 +181,38 @@ fn get_ffmpeg_command(
     }
 }
 
+/// Cut decoded audio to the part between `start_ms` and `end_ms`. Without manual trim
+/// times this is the item's duration, so tracks play back to back without gaps.
+/// Returns the number of samples per channel left in the file.
+async fn trim_pcm(path: &Path, start_ms: u32, end_ms: u32) -> anyhow::Result<u64> {
+    let to_bytes = |ms: u32| ms as u64 * SAMPLE_RATE / 1000 * BYTES_PER_SAMPLE;
+
+    let file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(path)
+        .await
+        .context("Failed to open temp.pcm for trimming")?;
+
+    let len = file.metadata().await?.len();
+    let end = to_bytes(end_ms);
+
+    if len > end {
+        file.set_len(end).await.context("Failed to trim temp.pcm")?;
+    }
+    let len = len.min(end);
+    let start = to_bytes(start_ms).min(len);
+
+    // Raw PCM has no header, dropping the first bytes is enough
+    if start > 0 {
+        let data = tokio::fs::read(path).await?;
+        tokio::fs::write(path, &data[start as usize..])
+            .await
+            .context("Failed to trim temp.pcm")?;
+    }
+
+    Ok((len - start) / BYTES_PER_SAMPLE)
+}
+
 pub struct Loader {
     session: Session,
 }
@@ -179,13 +227,34 @@ impl Loader {
         audio_item: &AudioItem,
         output_path: &Path,
         output_format: OutputFormat,
-        force_download: bool,
+        cmd: &DownloadArgs,
+        overrides: &TrackOverrides,
     ) -> anyhow::Result<()> {
         let config = PlayerConfig::default();
 
         let input_format = get_input_format(&config, audio_item);
 
-        if !force_download && output_path.exists() {
+        if cmd.dry_run {
+            let source = match input_format {
+                Some(format) => format!("{:?} at {} kbps", format, get_bitrate(&format)),
+                None => "unavailable".to_owned(),
+            };
+            let status = if !cmd.force_download && output_path.exists() {
+                ", exists"
+            } else {
+                ""
+            };
+            println!(
+                "{} ({}, source {}{})",
+                output_path.to_string_lossy(),
+                output_format.extension(),
+                source,
+                status
+            );
+            return Ok(());
+        }
+
+        if !cmd.force_download && output_path.exists() {
             println!("Skipping {}", output_path.to_string_lossy());
             return Ok(());
         }
@@ -230,9 +299,61 @@ impl Loader {
             bail!("Failed to download track");
         }
 
-        let mut cmd = get_ffmpeg_command(input_format, output_format, &output_path, &audio_item)?;
+        let (start_ms, end_ms) = overrides.trim_range(audio_item.duration_ms);
+        let sample_count = trim_pcm(Path::new("temp.pcm"), start_ms, end_ms).await?;
+        if sample_count == 0 {
+            bail!("No audio left to encode for {}", audio_item.name);
+        }
+
+        let mut tags = Tags::fetch(&self.session, audio_item).await;
+        overrides.apply(&mut tags);
+
+        // MP4 chapters are a separate track, ffmpeg has to add them while encoding
+        let chapters_file = Path::new("temp.chapters");
+        let use_chapters_file = output_format == OutputFormat::M4a && !tags.chapters.is_empty();
+        if use_chapters_file {
+            write_ffmetadata(chapters_file, &tags.chapters).await?;
+        }
+
+        let mut ffmpeg = get_ffmpeg_command(
+            input_format,
+            output_format,
+            output_path,
+            use_chapters_file.then_some(chapters_file),
+        )?;
+
+        ffmpeg.wait().await.context("Failed to wait for ffmpeg")?;
+
+        if use_chapters_file {
+            remove_file(chapters_file).await?;
+        }
+
+        tags.replay_gain = self
+            .get_replay_gain(audio_item, input_format, cmd.replaygain)
+            .await;
+        tags.sample_count = Some(sample_count);
+
+        let artist_tags = &cmd.artist_tags;
+        let lyrics_mode = cmd.lyrics;
+        if lyrics_mode != LyricsMode::None
+            && let Some(lyrics) = TrackLyrics::fetch(&self.session, audio_item).await
+        {
+            if lyrics_mode.embed() {
+                tags.lyrics = Some(lyrics.to_plain_text());
+            }
+            if lyrics_mode.lrc() {
+                lyrics
+                    .write_lrc(output_path, &tags.title, &artist_tags.join(&tags.artists))
+                    .await?;
+            }
+        }
+
+        write_tags(output_path, output_format, &tags, artist_tags)
+            .context("Failed to write tags")?;
 
-        cmd.wait().await.context("Failed to wait for ffmpeg")?;
+        if cmd.show_notes != ShowNotesMode::None {
+            write_show_notes(output_path, audio_item, cmd.show_notes).await?;
+        }
 
         let file = File::create("temp.pcm")
             .await
@@ -245,21 +366,60 @@ impl Loader {
         Ok(())
     }
 
+    async fn get_replay_gain(
+        &self,
+        audio_item: &AudioItem,
+        input_format: Option<AudioFileFormat>,
+        mode: ReplayGainMode,
+    ) -> Option<ReplayGain> {
+        if mode == ReplayGainMode::None {
+            return None;
+        }
+
+        let source = match input_format {
+            Some(format) => {
+                // Bitrate is in kbps, the audio file wants bytes per second
+                let bytes_per_second = get_bitrate(&format) as usize * 125;
+                ReplayGain::from_source(&self.session, audio_item, format, bytes_per_second)
+                    .await
+                    .unwrap_or_else(|e| {
+                        log::warn!("Failed to read normalisation data: {}", e);
+                        None
+                    })
+            }
+            None => None,
+        };
+
+        match (source, mode) {
+            (Some(gain), _) => Some(gain),
+            (None, ReplayGainMode::Analyze) => {
+                match ReplayGain::analyze(Path::new("temp.pcm")).await {
+                    Ok(gain) => Some(gain),
+                    Err(e) => {
+                        log::warn!("Failed to measure loudness: {}", e);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+
     pub async fn download_track_with_retry(
         &self,
         audio_item: &AudioItem,
         output_path: &Path,
         output_format: OutputFormat,
-        force_download: bool,
-        max_tries: u32,
+        cmd: &DownloadArgs,
+        overrides: &TrackOverrides,
     ) -> anyhow::Result<()> {
         let mut tries = 1;
         while let Err(e) = self
-            .download_track(audio_item, output_path, output_format, force_download)
+            .download_track(audio_item, output_path, output_format, cmd, overrides)
             .await
         {
             tries += 1;
-            if tries > max_tries {
+            if tries > cmd.max_tries {
                 log::error!("Reached max retries, aborting");
                 return Err(e);
             } else {
@@ -278,12 +438,11 @@ impl Loader {
         &self,
         tracks: impl Iterator<Item = &SpotifyId>,
         folder: &Path,
-        output_format: Option<OutputFormat>,
-        name_template: &str,
-        force_download: bool,
-        max_tries: u32,
-    ) -> anyhow::Result<()> {
+        cmd: &DownloadArgs,
+    ) -> anyhow::Result<Vec<ExportEntry>> {
+        let name_template = cmd.name_format.as_deref();
         let mut idx = 1;
+        let mut entries = vec![];
 
         for track_id in tracks {
             let item = match AudioItem::get_file(&self.session, *track_id).await {
@@ -291,37 +450,42 @@ impl Loader {
                 Err(e) => bail!("Failed to get audio item: {e}"),
             };
 
-            let output_format = output_format
+            let output_format = cmd
+                .format
                 .or_else(|| try_get_format_from_file_name(name_template))
                 .unwrap_or(OutputFormat::Opus);
             let extension = output_format.extension();
 
             let name = get_file_name(
+                &self.session,
                 &item,
                 name_template,
                 Some(idx),
-                if name_template.ends_with(&(".".to_owned() + extension)) {
+                if name_template.is_some_and(|t| t.ends_with(&(".".to_owned() + extension))) {
                     None
                 } else {
                     Some(&extension)
                 },
+                &cmd.name_style(),
             )
             .await;
 
+            let path = folder.join(Path::new(&name));
             self.download_track_with_retry(
                 &item,
-                folder.join(Path::new(&name)).as_path(),
+                path.as_path(),
                 output_format,
-                force_download,
-                max_tries,
+                cmd,
+                &TrackOverrides::default(),
             )
             .await?;
+            entries.push(ExportEntry::new(&item, &path, folder));
 
             idx += 1;
             tokio::time::sleep(std::time::Duration::from_millis(100)).await;
         }
 
-        Ok(())
+        Ok(entries)
     }
 
     async fn download_playlist(
@@ -335,21 +499,22 @@ impl Loader {
         let folder = cmd
             .common_args
             .output_path
+            .clone()
             .unwrap_or(PathBuf::from(plist.name()));
 
-        create_dir_all(&folder)
-            .await
-            .context("Failed to create playlist folder")?;
-
-        self.download_tracks(
-            plist.tracks(),
-            &folder,
-            cmd.format,
-            &cmd.name_format,
-            cmd.force_download,
-            cmd.max_tries,
-        )
-        .await
+        if !cmd.dry_run {
+            create_dir_all(&folder)
+                .await
+                .context("Failed to create playlist folder")?;
+        }
+
+        let entries = self.download_tracks(plist.tracks(), &folder, &cmd).await?;
+
+        if !cmd.dry_run {
+            write_playlists(&folder, plist.name(), &entries, &cmd.export).await?;
+        }
+
+        Ok(())
     }
 
     async fn download_album(
@@ -369,23 +534,24 @@ impl Loader {
         let folder = cmd
             .common_args
             .output_path
+            .clone()
             .unwrap_or(PathBuf::from(format!("{} - {}", artists, album.name)));
 
-        create_dir_all(&folder)
-            .await
-            .context("Failed to create album folder")?;
+        if !cmd.dry_run {
+            create_dir_all(&folder)
+                .await
+                .context("Failed to create album folder")?;
+        }
 
         println!("Downloading album {} by {}", album.name, artists);
 
-        self.download_tracks(
-            album.tracks(),
-            &folder,
-            cmd.format,
-            &cmd.name_format,
-            cmd.force_download,
-            cmd.max_tries,
-        )
-        .await
+        let entries = self.download_tracks(album.tracks(), &folder, &cmd).await?;
+
+        if !cmd.dry_run {
+            write_playlists(&folder, &album.name, &entries, &cmd.export).await?;
+        }
+
+        Ok(())
     }
 
     async fn download_show(
@@ -399,38 +565,101 @@ impl Loader {
         let folder = cmd
             .common_args
             .output_path
+            .clone()
             .unwrap_or(PathBuf::from(&show.name));
 
-        create_dir_all(&folder)
-            .await
-            .context("Failed to create show folder")?;
-
-        self.download_tracks(
-            show.episodes.iter(),
-            &folder,
-            cmd.format,
-            &cmd.name_format,
-            cmd.force_download,
-            cmd.max_tries,
-        )
-        .await
+        if !cmd.dry_run {
+            create_dir_all(&folder)
+                .await
+                .context("Failed to create show folder")?;
+        }
+
+        let episodes = self.select_episodes(&show, &folder, &cmd).await?;
+        self.download_tracks(episodes.iter(), &folder, &cmd).await?;
+
+        if cmd.rss
+            && !cmd.dry_run
+            && let Some(base_url) = &cmd.base_url
+        {
+            write_feed(&self.session, &folder, Some(&show), base_url).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Pick the episodes to download when `--latest`, `--since` or `--new-only` is set.
+    /// Stops at the first episode that doesn't match so old episodes aren't checked.
+    async fn select_episodes(
+        &self,
+        show: &Show,
+        folder: &Path,
+        cmd: &DownloadArgs,
+    ) -> anyhow::Result<Vec<SpotifyId>> {
+        let mut episodes: Vec<SpotifyId> = show.episodes.iter().copied().collect();
+        if cmd.latest.is_none() && cmd.since.is_none() && !cmd.new_only {
+            return Ok(episodes);
+        }
+
+        // Most shows list the newest episode first, check both ends to be sure
+        if let (Some(first), Some(last)) = (episodes.first(), episodes.last())
+            && first != last
+        {
+            let first = Episode::get(&self.session, first).await?;
+            let last = Episode::get(&self.session, last).await?;
+            if first.publish_time.as_timestamp_ms() < last.publish_time.as_timestamp_ms() {
+                episodes.reverse();
+            }
+        }
+
+        let downloaded = if cmd.new_only && folder.exists() {
+            downloaded_uris(folder).await?
+        } else {
+            HashSet::new()
+        };
+        let since_ms = cmd.since.map(|date| {
+            date.and_hms_opt(0, 0, 0)
+                .unwrap()
+                .and_utc()
+                .timestamp_millis()
+        });
+
+        let mut selected = vec![];
+        for id in &episodes {
+            if cmd.latest.is_some_and(|latest| selected.len() >= latest) {
+                break;
+            }
+            if cmd.new_only && downloaded.contains(&id.to_uri()?) {
+                break;
+            }
+            if let Some(since_ms) = since_ms {
+                let episode = Episode::get(&self.session, id).await?;
+                if episode.publish_time.as_timestamp_ms() < since_ms {
+                    break;
+                }
+            }
+            selected.push(*id);
+        }
+
+        println!("Selected {} of {} episodes", selected.len(), episodes.len());
+
+        Ok(selected)
     }
 
     async fn download_single_track(
         &self,
         item_ref: SpotifyId,
-        path: Option<&Path>,
-        output_format: Option<OutputFormat>,
-        name_template: &str,
-        force_download: bool,
-        max_tries: u32,
+        cmd: &DownloadArgs,
     ) -> anyhow::Result<()> {
+        let path = cmd.common_args.output_path.as_deref();
+        let name_template = cmd.name_format.as_deref();
+
         let item = match AudioItem::get_file(&self.session, item_ref).await {
             Ok(audio_item) => audio_item,
             Err(e) => bail!("Failed to get audio item: {e}"),
         };
 
-        let output_format = output_format
+        let output_format = cmd
+            .format
             .or_else(|| try_get_format_from_path(path))
             .or_else(|| try_get_format_from_file_name(name_template))
             .unwrap_or(OutputFormat::Opus);
@@ -441,30 +670,32 @@ impl Loader {
                     &item,
                     path,
                     output_format,
-                    force_download,
-                    max_tries,
+                    cmd,
+                    &TrackOverrides::default(),
                 )
                 .await
             }
             None => {
                 let extension = output_format.extension();
                 let name = get_file_name(
+                    &self.session,
                     &item,
                     name_template,
                     None,
-                    if name_template.ends_with(&(".".to_owned() + extension)) {
+                    if name_template.is_some_and(|t| t.ends_with(&(".".to_owned() + extension))) {
                         None
                     } else {
                         Some(&extension)
                     },
+                    &cmd.name_style(),
                 )
                 .await;
                 self.download_track_with_retry(
                     &item,
                     Path::new(&name),
                     output_format,
-                    force_download,
-                    max_tries,
+                    cmd,
+                    &TrackOverrides::default(),
                 )
                 .await
             }
@@ -472,44 +703,11 @@ impl Loader {
     }
 
     pub(crate) async fn download(&self, item_ref: SpotifyId, cmd: DownloadArgs) {
-        if let Some(filter) = &cmd.cleanup_regex {
-            match Regex::new(filter) {
-                Ok(re) => {
-                    _ = REGEX_FILTER.try_insert(re).unwrap();
-                }
-                Err(e) => {
-                    log::warn!("Invalid regex filter: {}", e);
-                }
-            };
-        }
-
-        let path = cmd.common_args.output_path.as_ref().map(|a| a.as_path());
-
         if let Err(e) = match item_ref.item_type {
-            SpotifyItemType::Track => {
-                self.download_single_track(
-                    item_ref,
-                    path,
-                    cmd.format,
-                    &cmd.name_format,
-                    cmd.force_download,
-                    cmd.max_tries,
-                )
-                .await
-            }
+            SpotifyItemType::Track => self.download_single_track(item_ref, &cmd).await,
             SpotifyItemType::Album => self.download_album(item_ref, cmd).await,
             SpotifyItemType::Playlist => self.download_playlist(item_ref, cmd).await,
-            SpotifyItemType::Episode => {
-                self.download_single_track(
-                    item_ref,
-                    path,
-                    cmd.format,
-                    &cmd.name_format,
-                    cmd.force_download,
-                    cmd.max_tries,
-                )
-                .await
-            }
+            SpotifyItemType::Episode => self.download_single_track(item_ref, &cmd).await,
             SpotifyItemType::Show => self.download_show(item_ref, cmd).await,
             _ => Err(anyhow!("Unsupported item type: {:?}", item_ref.item_type)),
         } {
@@ -0,0 +1,308 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::bail;
+use librespot::{
+    core::{Session, SpotifyId, spotify_id::SpotifyItemType},
+    metadata::{
+        Metadata, Playlist,
+        audio::{AudioItem, UniqueFields},
+    },
+};
+
+use crate::{
+    DedupeArgs, DiffArgs, MergeArgs, PlaylistCommand, SortArgs,
+    core::Loader,
+    parse_item_ref,
+    playlist::{SavedPlaylist, SavedTrack, fetch_source},
+};
+
+/// Order for `playlist sort`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum SortKey {
+    /// Custom name, or the Spotify title if there is none
+    Name,
+    Artist,
+    Album,
+    /// Date the track was added to the source playlist
+    Added,
+    /// Random order
+    Shuffle,
+}
+
+pub(crate) async fn run(loader: &Loader, command: PlaylistCommand) -> anyhow::Result<()> {
+    match command {
+        PlaylistCommand::Merge(cmd) => merge(cmd),
+        PlaylistCommand::Diff(cmd) => diff(loader.get_session(), cmd).await,
+        PlaylistCommand::Dedupe(cmd) => dedupe(cmd),
+        PlaylistCommand::Sort(cmd) => sort(loader.get_session(), cmd).await,
+    }
+}
+
+fn track_label(track: &SavedTrack) -> String {
+    match track.name() {
+        Some(name) => format!("{} ({})", name, track.uri()),
+        None => track.uri().to_owned(),
+    }
+}
+
+/// Combine playlists, the first file's title and defaults are kept. Included files of
+/// all of them are kept, with paths that work from the output file.
+fn merge(cmd: MergeArgs) -> anyhow::Result<()> {
+    let mut files = cmd.files.iter();
+    let Some(first) = files.next() else {
+        bail!("No playlists to merge");
+    };
+
+    let mut merged = SavedPlaylist::load(first)?;
+    merged.move_paths(first, &cmd.output)?;
+    let mut known = merged
+        .tracks
+        .iter()
+        .map(|track| track.uri().to_owned())
+        .collect::<HashSet<_>>();
+
+    for path in files {
+        let mut plist = SavedPlaylist::load(path)?;
+        plist.move_paths(path, &cmd.output)?;
+        for include in plist.include {
+            if !merged.include.contains(&include) {
+                merged.include.push(include);
+            }
+        }
+
+        let mut added = 0;
+        for track in plist.tracks {
+            if known.insert(track.uri().to_owned()) {
+                merged.tracks.push(track);
+                added += 1;
+            }
+        }
+        println!("Added {} tracks from {}", added, path.to_string_lossy());
+    }
+
+    if let Some(title) = cmd.title {
+        merged.title = title;
+    }
+    // The merged file doesn't match any single source anymore
+    merged.source = None;
+
+    merged.save(&cmd.output)
+}
+
+/// Save an edited playlist over `file`, or at `output` with its paths moved there
+fn save_as(mut plist: SavedPlaylist, file: &Path, output: Option<&Path>) -> anyhow::Result<()> {
+    match output {
+        Some(output) => {
+            plist.move_paths(file, output)?;
+            plist.save(output)
+        }
+        None => plist.save(file),
+    }
+}
+
+/// Print the tracks only in one of the playlists, `other` can be a file or a Spotify URL
+async fn diff(session: &Session, cmd: DiffArgs) -> anyhow::Result<()> {
+    let plist = SavedPlaylist::load(&cmd.file)?;
+
+    let other: Vec<(String, String)> = if Path::new(&cmd.other).is_file() {
+        SavedPlaylist::load(Path::new(&cmd.other))?
+            .tracks
+            .iter()
+            .filter(|track| !track.is_collection())
+            .map(|track| (track.uri().to_owned(), track_label(track)))
+            .collect()
+    } else {
+        let (_, ids) = fetch_source(session, parse_item_ref(&cmd.other)?).await?;
+        ids.iter()
+            .filter_map(|id| id.to_uri().ok())
+            .map(|uri| (uri.clone(), uri))
+            .collect()
+    };
+
+    // Albums, playlists and shows would never match a track, so they're left out
+    let tracks = plist
+        .tracks
+        .iter()
+        .filter(|track| !track.is_collection())
+        .collect::<Vec<_>>();
+    let ours = tracks
+        .iter()
+        .map(|track| track.uri())
+        .collect::<HashSet<_>>();
+    let theirs = other
+        .iter()
+        .map(|(uri, _)| uri.as_str())
+        .collect::<HashSet<_>>();
+
+    let mut changes = 0;
+    for track in tracks {
+        if !theirs.contains(track.uri()) {
+            println!("- {}", track_label(track));
+            changes += 1;
+        }
+    }
+    for (uri, label) in &other {
+        if !ours.contains(uri.as_str()) {
+            println!("+ {}", label);
+            changes += 1;
+        }
+    }
+
+    if changes == 0 {
+        println!("No differences");
+    }
+
+    Ok(())
+}
+
+/// Remove repeated URIs, keeping the first position. If a later copy has a custom
+/// name or overrides and the first one doesn't, the later copy is kept there instead.
+fn dedupe(cmd: DedupeArgs) -> anyhow::Result<()> {
+    let mut plist = SavedPlaylist::load(&cmd.file)?;
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    let mut tracks: Vec<SavedTrack> = vec![];
+
+    for track in plist.tracks {
+        match positions.get(track.uri()) {
+            Some(&idx) => {
+                println!("Removed duplicate {}", track_label(&track));
+                if !tracks[idx].is_customized() && track.is_customized() {
+                    tracks[idx] = track;
+                }
+            }
+            None => {
+                positions.insert(track.uri().to_owned(), tracks.len());
+                tracks.push(track);
+            }
+        }
+    }
+
+    plist.tracks = tracks;
+    save_as(plist, &cmd.file, cmd.output.as_deref())
+}
+
+async fn sort(session: &Session, cmd: SortArgs) -> anyhow::Result<()> {
+    let mut plist = SavedPlaylist::load(&cmd.file)?;
+    let tracks = std::mem::take(&mut plist.tracks);
+
+    let mut tracks = match cmd.by {
+        SortKey::Shuffle => {
+            let seed = cmd.seed.unwrap_or_else(|| {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(1, |time| time.as_nanos() as u64)
+            });
+            shuffle(tracks, seed)
+        }
+        SortKey::Added => {
+            let added = added_times(session, &plist).await?;
+            let mut tracks = tracks;
+            // Tracks that aren't in the source anymore go last
+            tracks.sort_by_key(|track| added.get(track.uri()).copied().unwrap_or(i64::MAX));
+            tracks
+        }
+        key => {
+            let mut keyed = vec![];
+            for track in tracks {
+                let sort_key = sort_key(session, &track, key).await?.to_lowercase();
+                keyed.push((sort_key, track));
+            }
+            keyed.sort_by(|(a, _), (b, _)| a.cmp(b));
+            keyed.into_iter().map(|(_, track)| track).collect()
+        }
+    };
+
+    if cmd.reverse {
+        tracks.reverse();
+    }
+    plist.tracks = tracks;
+    save_as(plist, &cmd.file, cmd.output.as_deref())
+}
+
+/// Name, artist or album for sorting, from the file if it has them or from Spotify
+async fn sort_key(session: &Session, track: &SavedTrack, key: SortKey) -> anyhow::Result<String> {
+    let overrides = track.overrides();
+    let info = track.info();
+
+    let known = match key {
+        SortKey::Name => track.name().or(overrides.title.as_deref()),
+        SortKey::Artist => overrides
+            .artist
+            .as_deref()
+            .or(info.map(|info| info.artists.as_str())),
+        SortKey::Album => overrides
+            .album
+            .as_deref()
+            .or(info.and_then(|info| info.album.as_deref())),
+        SortKey::Added | SortKey::Shuffle => None,
+    };
+    if let Some(known) = known {
+        return Ok(known.to_owned());
+    }
+
+    // Albums, playlists and shows sort by their own name
+    if track.is_collection() {
+        return Ok(fetch_source(session, track.id()?).await?.0);
+    }
+
+    let audio_item = AudioItem::get_file(session, track.id()?).await?;
+    Ok(match (key, &audio_item.unique_fields) {
+        (SortKey::Artist, UniqueFields::Track { artists, .. }) => artists
+            .first()
+            .map(|artist| artist.name.clone())
+            .unwrap_or_default(),
+        (SortKey::Album, UniqueFields::Track { album, .. }) => album.clone(),
+        (SortKey::Artist | SortKey::Album, UniqueFields::Episode { show_name, .. }) => {
+            show_name.clone()
+        }
+        _ => audio_item.name,
+    })
+}
+
+/// When each track was added to the playlist the file was saved from
+async fn added_times(
+    session: &Session,
+    plist: &SavedPlaylist,
+) -> anyhow::Result<HashMap<String, i64>> {
+    let source = plist
+        .source
+        .as_deref()
+        .and_then(|source| SpotifyId::from_uri(source).ok());
+    let Some(source) = source.filter(|source| source.item_type == SpotifyItemType::Playlist) else {
+        bail!(
+            "Sorting by added date needs a playlist `source`, save the file again from a Spotify playlist"
+        );
+    };
+
+    let playlist = Playlist::get(session, &source).await?;
+    Ok(playlist
+        .contents
+        .items
+        .iter()
+        .filter_map(|item| {
+            let uri = item.id.to_uri().ok()?;
+            Some((uri, item.attributes.timestamp.as_timestamp_ms()))
+        })
+        .collect())
+}
+
+/// Fisher-Yates with a small xorshift generator, good enough for playlists
+fn shuffle(mut tracks: Vec<SavedTrack>, seed: u64) -> Vec<SavedTrack> {
+    let mut state = seed.max(1);
+    let mut next = || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+
+    for i in (1..tracks.len()).rev() {
+        let j = (next() % (i as u64 + 1)) as usize;
+        tracks.swap(i, j);
+    }
+    tracks
+}
@@ -0,0 +1,159 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use itertools::Itertools;
+use librespot::metadata::audio::{AudioItem, UniqueFields};
+
+use crate::util::{encode_path, escape_xml};
+
+/// Playlist file formats written next to downloads
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum PlaylistFormat {
+    /// Extended M3U with UTF-8 paths
+    M3u8,
+    /// XML Shareable Playlist Format
+    Xspf,
+    Pls,
+}
+
+impl PlaylistFormat {
+    fn extension(&self) -> &str {
+        match self {
+            PlaylistFormat::M3u8 => "m3u8",
+            PlaylistFormat::Xspf => "xspf",
+            PlaylistFormat::Pls => "pls",
+        }
+    }
+}
+
+/// A downloaded file in playlist order
+pub(crate) struct ExportEntry {
+    /// Path relative to the playlist file
+    path: PathBuf,
+    title: String,
+    artist: String,
+    album: Option<String>,
+    duration_ms: u32,
+}
+
+impl ExportEntry {
+    pub fn new(audio_item: &AudioItem, path: &Path, folder: &Path) -> Self {
+        let (artist, album) = match &audio_item.unique_fields {
+            UniqueFields::Track { artists, album, .. } => (
+                artists.iter().map(|artist| &artist.name).join(", "),
+                Some(album.clone()),
+            ),
+            UniqueFields::Episode { show_name, .. } => (show_name.clone(), Some(show_name.clone())),
+        };
+
+        Self {
+            path: path.strip_prefix(folder).unwrap_or(path).to_path_buf(),
+            title: audio_item.name.clone(),
+            artist,
+            album,
+            duration_ms: audio_item.duration_ms,
+        }
+    }
+
+    fn display_title(&self) -> String {
+        if self.artist.is_empty() {
+            self.title.clone()
+        } else {
+            format!("{} - {}", self.artist, self.title)
+        }
+    }
+
+    /// Relative URL for formats that need one
+    fn location(&self) -> String {
+        self.path
+            .components()
+            .map(|part| encode_path(&part.as_os_str().to_string_lossy()))
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+/// Write playlist files for downloaded items into their folder, named after the folder
+pub(crate) async fn write_playlists(
+    folder: &Path,
+    title: &str,
+    entries: &[ExportEntry],
+    formats: &[PlaylistFormat],
+) -> anyhow::Result<()> {
+    if entries.is_empty() {
+        return Ok(());
+    }
+
+    let name = folder
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "playlist".to_owned());
+
+    for format in formats {
+        let contents = match format {
+            PlaylistFormat::M3u8 => to_m3u8(entries),
+            PlaylistFormat::Xspf => to_xspf(title, entries),
+            PlaylistFormat::Pls => to_pls(entries),
+        };
+
+        let path = folder.join(format!("{}.{}", name, format.extension()));
+        tokio::fs::write(&path, contents)
+            .await
+            .with_context(|| format!("Failed to write {}", path.to_string_lossy()))?;
+        println!("Saved playlist to {}", path.to_string_lossy());
+    }
+
+    Ok(())
+}
+
+fn to_m3u8(entries: &[ExportEntry]) -> String {
+    let mut m3u = "#EXTM3U\n".to_owned();
+    for entry in entries {
+        m3u += &format!(
+            "#EXTINF:{},{}\n{}\n",
+            entry.duration_ms / 1000,
+            entry.display_title(),
+            entry.path.to_string_lossy()
+        );
+    }
+    m3u
+}
+
+fn to_xspf(title: &str, entries: &[ExportEntry]) -> String {
+    let mut xspf = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n  \
+         <title>{}</title>\n  <trackList>\n",
+        escape_xml(title)
+    );
+    for entry in entries {
+        xspf += "    <track>\n";
+        xspf += &format!(
+            "      <location>{}</location>\n",
+            escape_xml(&entry.location())
+        );
+        xspf += &format!("      <title>{}</title>\n", escape_xml(&entry.title));
+        if !entry.artist.is_empty() {
+            xspf += &format!("      <creator>{}</creator>\n", escape_xml(&entry.artist));
+        }
+        if let Some(album) = &entry.album {
+            xspf += &format!("      <album>{}</album>\n", escape_xml(album));
+        }
+        xspf += &format!("      <duration>{}</duration>\n", entry.duration_ms);
+        xspf += "    </track>\n";
+    }
+    xspf += "  </trackList>\n</playlist>\n";
+    xspf
+}
+
+fn to_pls(entries: &[ExportEntry]) -> String {
+    let mut pls = "[playlist]\n".to_owned();
+    for (i, entry) in entries.iter().enumerate() {
+        let n = i + 1;
+        pls += &format!("File{}={}\n", n, entry.path.to_string_lossy());
+        pls += &format!("Title{}={}\n", n, entry.display_title());
+        pls += &format!("Length{}={}\n", n, entry.duration_ms / 1000);
+    }
+    pls += &format!("NumberOfEntries={}\nVersion=2\n", entries.len());
+    pls
+}
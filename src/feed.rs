@@ -0,0 +1,235 @@
+use std::path::Path;
+
+use anyhow::Context;
+use chrono::{NaiveDate, Utc};
+use librespot::{
+    core::{Session, SpotifyId},
+    metadata::{Episode, Metadata, Show},
+};
+use lofty::prelude::*;
+use tokio::fs;
+
+use crate::{
+    FeedArgs,
+    core::{Loader, OutputFormat},
+    podcast::html_to_text,
+    rename::list_audio_files,
+    util::{encode_path, escape_xml},
+};
+
+/// Feed file written into the show folder
+pub(crate) const FEED_FILE: &str = "feed.xml";
+/// Show cover saved next to the feed
+const COVER_FILE: &str = "cover.jpg";
+
+/// A downloaded episode, read back from its tags
+struct FeedItem {
+    file_name: String,
+    mime_type: String,
+    size: u64,
+    title: String,
+    show: Option<String>,
+    description: Option<String>,
+    date: Option<NaiveDate>,
+    duration_secs: u64,
+    uri: Option<String>,
+}
+
+impl FeedItem {
+    async fn read(path: &Path) -> anyhow::Result<Self> {
+        let tagged_file = lofty::read_from_path(path)?;
+        let tag = tagged_file.primary_tag();
+        let text = |key: ItemKey| tag.and_then(|tag| tag.get_string(&key)).map(str::to_owned);
+
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let format = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(OutputFormat::from_extension)
+            .unwrap_or_default();
+
+        Ok(Self {
+            title: text(ItemKey::TrackTitle).unwrap_or_else(|| {
+                path.file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            }),
+            show: text(ItemKey::AlbumTitle),
+            description: text(ItemKey::Description),
+            date: text(ItemKey::RecordingDate)
+                .and_then(|date| NaiveDate::parse_from_str(&date, "%Y-%m-%d").ok()),
+            duration_secs: tagged_file.properties().duration().as_secs(),
+            uri: text(ItemKey::Comment).filter(|uri| uri.starts_with("spotify:")),
+            size: fs::metadata(path).await?.len(),
+            mime_type: format.mime_type().to_owned(),
+            file_name,
+        })
+    }
+}
+
+pub(crate) async fn feed(loader: &Loader, cmd: FeedArgs) -> anyhow::Result<()> {
+    write_feed(loader.get_session(), &cmd.folder, None, &cmd.base_url).await
+}
+
+/// Write a podcast RSS feed for the episodes in a folder. Show details come from `show`
+/// if we have it, otherwise from the episode tags.
+pub(crate) async fn write_feed(
+    session: &Session,
+    folder: &Path,
+    show: Option<&Show>,
+    base_url: &str,
+) -> anyhow::Result<()> {
+    let mut items = vec![];
+    for path in list_audio_files(folder).await? {
+        match FeedItem::read(&path).await {
+            Ok(item) => items.push(item),
+            Err(e) => log::warn!("Failed to read {}: {}", path.to_string_lossy(), e),
+        }
+    }
+    // Newest episodes first like a regular feed
+    items.sort_by_key(|item| std::cmp::Reverse(item.date));
+
+    let folder_name = folder
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let title = show
+        .map(|show| show.name.clone())
+        .or_else(|| items.iter().find_map(|item| item.show.clone()))
+        .unwrap_or(folder_name);
+    let description = show
+        .map(|show| html_to_text(&show.description, false))
+        .unwrap_or_else(|| title.clone());
+
+    let has_cover = save_cover(session, folder, show, &items).await;
+
+    let link = |file_name: &str| {
+        format!(
+            "{}/{}",
+            base_url.trim_end_matches('/'),
+            encode_path(file_name)
+        )
+    };
+
+    let mut rss = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <rss version=\"2.0\" xmlns:itunes=\"http://www.itunes.com/dtds/podcast-1.0.dtd\">\n\
+         <channel>\n",
+    );
+    rss += &format!("  <title>{}</title>\n", escape_xml(&title));
+    rss += &format!(
+        "  <description>{}</description>\n",
+        escape_xml(&description)
+    );
+    rss += &format!("  <link>{}</link>\n", escape_xml(&link("")));
+    rss += &format!(
+        "  <lastBuildDate>{}</lastBuildDate>\n",
+        Utc::now().to_rfc2822()
+    );
+    if let Some(show) = show {
+        rss += &format!(
+            "  <itunes:author>{}</itunes:author>\n",
+            escape_xml(&show.publisher)
+        );
+    }
+    if has_cover {
+        let cover = escape_xml(&link(COVER_FILE));
+        rss += &format!("  <itunes:image href=\"{}\"/>\n", cover);
+        rss += &format!(
+            "  <image>\n    <url>{}</url>\n    <title>{}</title>\n    <link>{}</link>\n  </image>\n",
+            cover,
+            escape_xml(&title),
+            escape_xml(&link(""))
+        );
+    }
+
+    for item in &items {
+        rss += "  <item>\n";
+        rss += &format!("    <title>{}</title>\n", escape_xml(&item.title));
+        if let Some(description) = &item.description {
+            rss += &format!(
+                "    <description>{}</description>\n",
+                escape_xml(description)
+            );
+        }
+        if let Some(date) = item.date {
+            let date = date.and_hms_opt(0, 0, 0).unwrap().and_utc();
+            rss += &format!("    <pubDate>{}</pubDate>\n", date.to_rfc2822());
+        }
+        rss += &format!(
+            "    <guid isPermaLink=\"false\">{}</guid>\n",
+            escape_xml(item.uri.as_deref().unwrap_or(&item.file_name))
+        );
+        rss += &format!(
+            "    <enclosure url=\"{}\" length=\"{}\" type=\"{}\"/>\n",
+            escape_xml(&link(&item.file_name)),
+            item.size,
+            item.mime_type
+        );
+        rss += &format!(
+            "    <itunes:duration>{}</itunes:duration>\n",
+            item.duration_secs
+        );
+        rss += "  </item>\n";
+    }
+    rss += "</channel>\n</rss>\n";
+
+    let path = folder.join(FEED_FILE);
+    fs::write(&path, rss)
+        .await
+        .context("Failed to write feed")?;
+    println!("Saved feed to {}", path.to_string_lossy());
+
+    Ok(())
+}
+
+/// Save the show cover next to the feed, returns whether there is one
+async fn save_cover(
+    session: &Session,
+    folder: &Path,
+    show: Option<&Show>,
+    items: &[FeedItem],
+) -> bool {
+    let path = folder.join(COVER_FILE);
+    if path.exists() {
+        return true;
+    }
+
+    // Episodes have the show cover, so the first one works without the show
+    let image = match show {
+        Some(show) => show.covers.iter().max_by_key(|image| image.width).cloned(),
+        None => {
+            let id = items
+                .iter()
+                .find_map(|item| SpotifyId::from_uri(item.uri.as_deref()?).ok());
+            match id {
+                Some(id) => Episode::get(session, &id).await.ok().and_then(|episode| {
+                    episode
+                        .covers
+                        .iter()
+                        .max_by_key(|image| image.width)
+                        .cloned()
+                }),
+                None => None,
+            }
+        }
+    };
+    let Some(image) = image else {
+        return false;
+    };
+
+    let result = match session.spclient().get_image(&image.id).await {
+        Ok(data) => fs::write(&path, &data).await.map_err(anyhow::Error::from),
+        Err(e) => Err(e.into()),
+    };
+    match result {
+        Ok(()) => true,
+        Err(e) => {
+            log::warn!("Failed to save show cover: {}", e);
+            false
+        }
+    }
+}
@@ -0,0 +1,532 @@
+use std::{collections::HashSet, io::Write, path::Path};
+
+use anyhow::{Context, bail};
+use librespot::core::{Session, SpotifyId, spotify_id::SpotifyItemType};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::Value;
+
+use crate::{
+    ImportArgs,
+    core::Loader,
+    m3u::parse_m3u,
+    parse_item_ref,
+    playlist::{PlaylistFileFormat, SavedPlaylist, SavedTrack},
+    search::{FoundTrack, search_tracks},
+};
+
+/// Track number at the start of a file name, like `01 - ` or `3. `
+static TRACK_NUMBER: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\d{1,3}[\s.\-_]+").unwrap());
+/// Bracketed parts and ` - Remastered` style suffixes that other services add to titles
+static TITLE_EXTRAS: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\s*(\([^)]*\)|\[[^\]]*\]|\s-\s.*$)").unwrap());
+
+// Column and key names used by common exports, compared in lowercase
+const URI_KEYS: &[&str] = &[
+    "uri",
+    "track uri",
+    "trackuri",
+    "track_uri",
+    "spotify uri",
+    "spotify_uri",
+    "url",
+    "link",
+];
+const TITLE_KEYS: &[&str] = &[
+    "title",
+    "name",
+    "track",
+    "track name",
+    "trackname",
+    "track_name",
+    "song",
+];
+const ARTIST_KEYS: &[&str] = &[
+    "artist",
+    "artists",
+    "artist name",
+    "artist name(s)",
+    "artistname",
+    "artist_name",
+];
+const ALBUM_KEYS: &[&str] = &["album", "album name", "albumname", "album_name"];
+const DURATION_KEYS: &[&str] = &["duration", "duration (ms)", "duration_ms", "length"];
+
+/// How many search results are compared for each entry
+const SEARCH_LIMIT: u32 = 5;
+
+/// A track read from an import file
+#[derive(Default)]
+struct ImportEntry {
+    /// Where the entry is in the file, for messages
+    position: String,
+    uri: Option<SpotifyId>,
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    duration_ms: Option<u32>,
+}
+
+impl ImportEntry {
+    fn label(&self) -> String {
+        match (&self.artist, &self.title) {
+            (Some(artist), Some(title)) => format!("{} - {}", artist, title),
+            (None, Some(title)) => title.clone(),
+            _ => "(no title)".to_owned(),
+        }
+    }
+}
+
+enum Resolution {
+    Found(SpotifyId),
+    /// Nothing good enough, with the best candidate and its score if there was one
+    Unresolved(Option<(FoundTrack, f64)>),
+}
+
+/// Turn an M3U, CSV or JSON playlist into a saved playlist, looking up tracks that
+/// don't have a Spotify URI by their title and artist
+pub(crate) async fn import(loader: &Loader, cmd: ImportArgs) -> anyhow::Result<()> {
+    let session = loader.get_session();
+    let text = std::fs::read_to_string(&cmd.file)
+        .with_context(|| format!("Failed to read {}", cmd.file.to_string_lossy()))?;
+
+    let extension = cmd
+        .file
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let (file_title, entries) = match extension.as_str() {
+        "m3u" | "m3u8" => import_m3u(&text),
+        "csv" => (None, parse_csv(&text)?),
+        "json" => parse_json(&text)?,
+        _ => bail!(
+            "Can't import {}, supported formats are M3U, CSV and JSON",
+            cmd.file.to_string_lossy()
+        ),
+    };
+
+    let title = cmd
+        .title
+        .or(file_title)
+        .or_else(|| {
+            cmd.file
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+        })
+        .unwrap_or_default();
+
+    let mut tracks = vec![];
+    let mut known = HashSet::new();
+    let mut unresolved = vec![];
+    for entry in &entries {
+        match resolve(session, entry, cmd.min_score).await? {
+            Resolution::Found(id) => {
+                if !known.insert(id) {
+                    continue;
+                }
+                let number = tracks.len() as u32 + 1;
+                if let Some(track) = SavedTrack::fetch(
+                    session,
+                    id,
+                    number,
+                    cmd.details_args.details,
+                    cmd.details_args.name_format.as_deref(),
+                )
+                .await?
+                {
+                    tracks.push(track);
+                }
+            }
+            Resolution::Unresolved(best) => {
+                println!("Not found: {} ({})", entry.label(), entry.position);
+                unresolved.push((entry, best));
+            }
+        }
+    }
+
+    println!(
+        "Found {} of {} tracks, {} unresolved",
+        entries.len() - unresolved.len(),
+        entries.len(),
+        unresolved.len()
+    );
+
+    let mut plist = SavedPlaylist::new(title, tracks);
+    plist.save(&cmd.output)?;
+
+    // Only TOML has comments, other formats just get the list printed above
+    if !unresolved.is_empty()
+        && PlaylistFileFormat::from_path(&cmd.output) == Some(PlaylistFileFormat::Toml)
+    {
+        write_unresolved(&cmd.output, &unresolved)?;
+    }
+
+    Ok(())
+}
+
+/// List entries we couldn't find at the end of the playlist as comments, so they can be
+/// looked up by hand and added to `tracks`
+fn write_unresolved(
+    path: &Path,
+    unresolved: &[(&ImportEntry, Option<(FoundTrack, f64)>)],
+) -> anyhow::Result<()> {
+    let mut comments = String::from("\n# Not found on Spotify, add these to `tracks` by hand:\n");
+    for (entry, best) in unresolved {
+        comments += &format!("# {}: {}", entry.position, entry.label());
+        if let Some((track, score)) = best {
+            comments += &format!(
+                " (closest: {} - {}, {}, score {:.2})",
+                track.artist_names(),
+                track.name,
+                track.uri,
+                score
+            );
+        }
+        comments += "\n";
+    }
+
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to write {}", path.to_string_lossy()))?;
+    file.write_all(comments.as_bytes())?;
+
+    Ok(())
+}
+
+async fn resolve(
+    session: &Session,
+    entry: &ImportEntry,
+    min_score: f64,
+) -> anyhow::Result<Resolution> {
+    if let Some(uri) = entry.uri {
+        return Ok(Resolution::Found(uri));
+    }
+    let Some(title) = &entry.title else {
+        return Ok(Resolution::Unresolved(None));
+    };
+
+    let query = match &entry.artist {
+        Some(artist) => format!("{} {}", artist, strip_extras(title)),
+        None => strip_extras(title),
+    };
+    let results = match search_tracks(session, &query, SEARCH_LIMIT).await {
+        Ok(results) => results,
+        Err(e) => {
+            log::warn!("Search for {} failed: {}", entry.label(), e);
+            return Ok(Resolution::Unresolved(None));
+        }
+    };
+
+    let best = results
+        .into_iter()
+        .map(|track| {
+            let score = match_score(entry, &track);
+            (track, score)
+        })
+        .max_by(|(_, a), (_, b)| a.total_cmp(b));
+
+    Ok(match best {
+        Some((track, score)) if score >= min_score => match SpotifyId::from_uri(&track.uri) {
+            Ok(id) => Resolution::Found(id),
+            Err(_) => Resolution::Unresolved(Some((track, score))),
+        },
+        best => Resolution::Unresolved(best),
+    })
+}
+
+/// How well a search result matches an entry, from 0 to 1
+fn match_score(entry: &ImportEntry, track: &FoundTrack) -> f64 {
+    let title = entry.title.as_deref().unwrap_or_default();
+    let title_score = word_similarity(title, &track.name).max(word_similarity(
+        &strip_extras(title),
+        &strip_extras(&track.name),
+    ));
+
+    let mut score = match &entry.artist {
+        // Results list every artist, so only check that the ones we have are there
+        Some(artist) => title_score * 0.6 + word_coverage(artist, &track.artist_names()) * 0.4,
+        None => title_score,
+    };
+    if let Some(album) = &entry.album
+        && word_similarity(album, &track.album.name) < 0.5
+    {
+        score *= 0.95;
+    }
+    if let Some(duration_ms) = entry.duration_ms
+        && duration_ms.abs_diff(track.duration_ms) > 10_000
+    {
+        score *= 0.8;
+    }
+
+    score
+}
+
+fn words(text: &str) -> HashSet<String> {
+    deunicode::deunicode(text)
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Dice coefficient of the words in both strings
+fn word_similarity(a: &str, b: &str) -> f64 {
+    let (a, b) = (words(a), words(b));
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    2.0 * a.intersection(&b).count() as f64 / (a.len() + b.len()) as f64
+}
+
+/// Share of the words in `part` that are also in `whole`
+fn word_coverage(part: &str, whole: &str) -> f64 {
+    let (part, whole) = (words(part), words(whole));
+    if part.is_empty() {
+        return 0.0;
+    }
+    part.intersection(&whole).count() as f64 / part.len() as f64
+}
+
+fn strip_extras(title: &str) -> String {
+    TITLE_EXTRAS.replace_all(title, "").trim().to_owned()
+}
+
+/// A track URI or link, other Spotify items can't go in a playlist file
+fn spotify_track(text: &str) -> Option<SpotifyId> {
+    let text = text.trim();
+    if !text.starts_with("spotify:") && !text.contains("spotify.com/") {
+        return None;
+    }
+    parse_item_ref(text).ok().filter(|id| {
+        matches!(
+            id.item_type,
+            SpotifyItemType::Track | SpotifyItemType::Episode
+        )
+    })
+}
+
+/// Split `Artist - Title` the way most players write it
+fn split_display_name(name: &str) -> (Option<String>, Option<String>) {
+    let name = name.trim();
+    match name.split_once(" - ") {
+        Some((artist, title)) => (
+            Some(artist.trim().to_owned()),
+            Some(title.trim().to_owned()),
+        ),
+        None if name.is_empty() => (None, None),
+        None => (None, Some(name.to_owned())),
+    }
+}
+
+/// Durations are `m:ss`, milliseconds or seconds. Plain numbers over 10 hours are
+/// taken as milliseconds.
+fn parse_duration(text: &str) -> Option<u32> {
+    let text = text.trim();
+    if let Some((minutes, seconds)) = text.split_once(':') {
+        let minutes = minutes.parse::<u32>().ok()?;
+        let seconds = seconds.parse::<f64>().ok()?;
+        return Some(minutes * 60_000 + (seconds * 1000.0) as u32);
+    }
+    let number = text.parse::<f64>().ok()?;
+    if number > 36_000.0 {
+        Some(number as u32)
+    } else {
+        Some((number * 1000.0) as u32)
+    }
+}
+
+fn import_m3u(text: &str) -> (Option<String>, Vec<ImportEntry>) {
+    let (title, m3u_entries) = parse_m3u(text);
+
+    let entries = m3u_entries
+        .into_iter()
+        .map(|entry| {
+            // Without a display name the file name is the best guess
+            let name = entry.name.unwrap_or_else(|| {
+                let stem = Path::new(&entry.location)
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                TRACK_NUMBER.replace(&stem, "").into_owned()
+            });
+            let (artist, title) = split_display_name(&name);
+
+            ImportEntry {
+                position: format!("line {}", entry.line),
+                uri: spotify_track(&entry.location),
+                title,
+                artist,
+                duration_ms: entry.duration_ms,
+                ..Default::default()
+            }
+        })
+        .collect();
+
+    (title, entries)
+}
+
+fn parse_csv(text: &str) -> anyhow::Result<Vec<ImportEntry>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(text.as_bytes());
+    let headers = reader
+        .headers()?
+        .iter()
+        .map(|header| header.trim().to_lowercase())
+        .collect::<Vec<_>>();
+    let column = |keys: &[&str]| {
+        headers
+            .iter()
+            .position(|header| keys.contains(&header.as_str()))
+    };
+
+    let uri = column(URI_KEYS);
+    let title = column(TITLE_KEYS);
+    let artist = column(ARTIST_KEYS);
+    let album = column(ALBUM_KEYS);
+    let duration = column(DURATION_KEYS);
+    if uri.is_none() && title.is_none() {
+        bail!(
+            "CSV file needs a URI or title column, found {}",
+            headers.join(", ")
+        );
+    }
+
+    let mut entries = vec![];
+    for record in reader.records() {
+        let record = record?;
+        let field = |idx: Option<usize>| {
+            idx.and_then(|idx| record.get(idx))
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_owned)
+        };
+        let line = record.position().map_or(0, |pos| pos.line());
+
+        entries.push(ImportEntry {
+            position: format!("line {}", line),
+            uri: field(uri).as_deref().and_then(spotify_track),
+            title: field(title),
+            artist: field(artist),
+            album: field(album),
+            duration_ms: field(duration).as_deref().and_then(parse_duration),
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Reads a list of tracks, an object with a `tracks` or `items` list, or a Spotify
+/// account data export with `playlists`. Returns the playlist name if there is one.
+fn parse_json(text: &str) -> anyhow::Result<(Option<String>, Vec<ImportEntry>)> {
+    let root: Value = serde_json::from_str(text).context("Invalid JSON")?;
+
+    let name = root.get("name").and_then(Value::as_str).map(str::to_owned);
+    let (name, items) = match &root {
+        Value::Array(items) => (None, items.iter().collect::<Vec<_>>()),
+        Value::Object(object) => {
+            if let Some(Value::Array(playlists)) = object.get("playlists") {
+                let name = playlists
+                    .first()
+                    .and_then(|plist| plist.get("name"))
+                    .and_then(Value::as_str)
+                    .map(str::to_owned);
+                let items = playlists
+                    .iter()
+                    .filter_map(|plist| plist.get("items").and_then(Value::as_array))
+                    .flatten()
+                    .collect();
+                (name, items)
+            } else if let Some(Value::Array(items)) =
+                object.get("tracks").or_else(|| object.get("items"))
+            {
+                (name, items.iter().collect())
+            } else {
+                bail!("JSON file needs a list of tracks, or a `tracks` or `items` list");
+            }
+        }
+        _ => bail!("JSON file needs a list of tracks"),
+    };
+
+    let entries = items
+        .into_iter()
+        .enumerate()
+        .map(|(idx, item)| json_entry(idx + 1, item))
+        .collect();
+
+    Ok((name, entries))
+}
+
+fn json_entry(number: usize, item: &Value) -> ImportEntry {
+    let position = format!("entry {}", number);
+
+    let item = match item {
+        Value::String(text) => {
+            return match spotify_track(text) {
+                Some(uri) => ImportEntry {
+                    position,
+                    uri: Some(uri),
+                    ..Default::default()
+                },
+                None => {
+                    let (artist, title) = split_display_name(text);
+                    ImportEntry {
+                        position,
+                        title,
+                        artist,
+                        ..Default::default()
+                    }
+                }
+            };
+        }
+        // Playlist items from Spotify wrap the track
+        Value::Object(object) => match object.get("track") {
+            Some(Value::Object(track)) => track,
+            _ => object,
+        },
+        _ => {
+            return ImportEntry {
+                position,
+                ..Default::default()
+            };
+        }
+    };
+
+    let lookup = |keys: &[&str]| {
+        item.iter()
+            .find(|(key, _)| keys.contains(&key.to_lowercase().as_str()))
+            .map(|(_, value)| value)
+    };
+    let text = |keys: &[&str]| match lookup(keys)? {
+        Value::String(text) if !text.trim().is_empty() => Some(text.trim().to_owned()),
+        Value::Object(object) => object.get("name")?.as_str().map(str::to_owned),
+        Value::Array(values) => {
+            let names = values
+                .iter()
+                .filter_map(|value| match value {
+                    Value::String(text) => Some(text.as_str()),
+                    value => value.get("name")?.as_str(),
+                })
+                .collect::<Vec<_>>();
+            (!names.is_empty()).then(|| names.join(", "))
+        }
+        _ => None,
+    };
+
+    ImportEntry {
+        position,
+        uri: text(URI_KEYS).as_deref().and_then(spotify_track),
+        title: text(TITLE_KEYS),
+        artist: text(ARTIST_KEYS),
+        album: text(ALBUM_KEYS),
+        duration_ms: match lookup(DURATION_KEYS) {
+            Some(Value::Number(number)) => {
+                number.as_f64().and_then(|n| parse_duration(&n.to_string()))
+            }
+            Some(Value::String(text)) => parse_duration(text),
+            _ => None,
+        },
+    }
+}
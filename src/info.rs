@@ -0,0 +1,266 @@
+use anyhow::bail;
+use librespot::{
+    core::{Session, SpotifyId, spotify_id::SpotifyItemType},
+    metadata::{
+        Album, Artist, Metadata, Playlist, Show,
+        audio::{AudioItem, UniqueFields},
+    },
+    playback::config::PlayerConfig,
+};
+use serde::Serialize;
+
+use crate::{
+    InfoArgs,
+    core::{Loader, get_bitrate},
+    metadata::{format_duration, get_input_format},
+    parse_item_ref,
+    tags::format_date,
+};
+
+/// Everything `daytrip info` knows about an item, empty fields are left out
+#[derive(Serialize, Default)]
+struct ItemInfo {
+    uri: String,
+    #[serde(rename = "type")]
+    item_type: String,
+    name: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    artists: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    album: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    show: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    publisher: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration_ms: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tracks: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    discs: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    episodes: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    explicit: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    popularity: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    available: Option<bool>,
+    /// Why the item can't be played, if it can't
+    #[serde(skip_serializing_if = "Option::is_none")]
+    restriction: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    formats: Vec<FormatInfo>,
+}
+
+#[derive(Serialize)]
+struct FormatInfo {
+    format: String,
+    bitrate_kbps: u32,
+    /// The format `get` would download
+    selected: bool,
+}
+
+pub(crate) async fn info(loader: &Loader, cmd: InfoArgs) -> anyhow::Result<()> {
+    let item_ref = parse_item_ref(&cmd.url)?;
+    let info = fetch_info(loader.get_session(), item_ref).await?;
+
+    if cmd.json {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+    } else {
+        print_table(&info);
+    }
+
+    Ok(())
+}
+
+async fn fetch_info(session: &Session, item_ref: SpotifyId) -> anyhow::Result<ItemInfo> {
+    let uri = item_ref.to_uri()?;
+
+    Ok(match item_ref.item_type {
+        SpotifyItemType::Track | SpotifyItemType::Episode => {
+            let audio_item = AudioItem::get_file(session, item_ref).await?;
+            audio_item_info(uri, &audio_item)
+        }
+        SpotifyItemType::Album => {
+            let album = Album::get(session, &item_ref).await?;
+            ItemInfo {
+                uri,
+                item_type: "album".to_owned(),
+                artists: album
+                    .artists
+                    .iter()
+                    .map(|artist| artist.name.clone())
+                    .collect(),
+                label: Some(album.label.clone()).filter(|label| !label.is_empty()),
+                date: format_date(album.date.as_timestamp_ms()),
+                tracks: Some(album.tracks().count()),
+                discs: Some(album.discs.len()),
+                popularity: Some(album.popularity),
+                name: album.name,
+                ..Default::default()
+            }
+        }
+        SpotifyItemType::Playlist => {
+            let plist = Playlist::get(session, &item_ref).await?;
+            ItemInfo {
+                uri,
+                item_type: "playlist".to_owned(),
+                name: plist.name().to_owned(),
+                tracks: Some(plist.tracks().len()),
+                ..Default::default()
+            }
+        }
+        SpotifyItemType::Artist => {
+            let artist = Artist::get(session, &item_ref).await?;
+            ItemInfo {
+                uri,
+                item_type: "artist".to_owned(),
+                name: artist.name,
+                popularity: Some(artist.popularity),
+                ..Default::default()
+            }
+        }
+        SpotifyItemType::Show => {
+            let show = Show::get(session, &item_ref).await?;
+            ItemInfo {
+                uri,
+                item_type: "show".to_owned(),
+                name: show.name,
+                publisher: Some(show.publisher),
+                episodes: Some(show.episodes.len()),
+                explicit: Some(show.is_explicit),
+                language: Some(show.language).filter(|language| !language.is_empty()),
+                ..Default::default()
+            }
+        }
+        _ => bail!("Unsupported item type: {:?}", item_ref.item_type),
+    })
+}
+
+fn audio_item_info(uri: String, audio_item: &AudioItem) -> ItemInfo {
+    let selected = get_input_format(&PlayerConfig::default(), audio_item);
+    let mut formats = audio_item
+        .files
+        .keys()
+        .map(|format| FormatInfo {
+            format: format!("{:?}", format),
+            bitrate_kbps: get_bitrate(format),
+            selected: Some(*format) == selected,
+        })
+        .collect::<Vec<_>>();
+    formats.sort_by(|a, b| {
+        b.bitrate_kbps
+            .cmp(&a.bitrate_kbps)
+            .then_with(|| a.format.cmp(&b.format))
+    });
+
+    let mut info = ItemInfo {
+        uri,
+        name: audio_item.name.clone(),
+        duration_ms: Some(audio_item.duration_ms),
+        explicit: Some(audio_item.is_explicit),
+        available: Some(audio_item.availability.is_ok()),
+        restriction: audio_item
+            .availability
+            .as_ref()
+            .err()
+            .map(|e| e.to_string()),
+        language: Some(audio_item.language.join(", ")).filter(|language| !language.is_empty()),
+        formats,
+        ..Default::default()
+    };
+
+    match &audio_item.unique_fields {
+        UniqueFields::Track {
+            artists,
+            album,
+            popularity,
+            ..
+        } => {
+            info.item_type = "track".to_owned();
+            info.artists = artists.iter().map(|artist| artist.name.clone()).collect();
+            info.album = Some(album.clone());
+            info.popularity = Some(*popularity as i32);
+        }
+        UniqueFields::Episode {
+            publish_time,
+            show_name,
+            ..
+        } => {
+            info.item_type = "episode".to_owned();
+            info.show = Some(show_name.clone());
+            info.date = format_date(publish_time.as_timestamp_ms());
+        }
+    }
+
+    info
+}
+
+fn print_table(info: &ItemInfo) {
+    let yes_no = |value: bool| if value { "yes" } else { "no" }.to_owned();
+
+    let rows = [
+        ("Name", Some(info.name.clone())),
+        ("Type", Some(info.item_type.clone())),
+        ("URI", Some(info.uri.clone())),
+        (
+            "Artists",
+            Some(info.artists.join(", ")).filter(|a| !a.is_empty()),
+        ),
+        ("Album", info.album.clone()),
+        ("Show", info.show.clone()),
+        ("Publisher", info.publisher.clone()),
+        ("Label", info.label.clone()),
+        ("Date", info.date.clone()),
+        ("Duration", info.duration_ms.map(format_duration)),
+        ("Tracks", info.tracks.map(|count| count.to_string())),
+        ("Discs", info.discs.map(|count| count.to_string())),
+        ("Episodes", info.episodes.map(|count| count.to_string())),
+        ("Explicit", info.explicit.map(yes_no)),
+        ("Popularity", info.popularity.map(|value| value.to_string())),
+        ("Language", info.language.clone()),
+        ("Available", info.available.map(yes_no)),
+        ("Restriction", info.restriction.clone()),
+    ];
+
+    let width = rows.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+    for (label, value) in &rows {
+        if let Some(value) = value {
+            println!("{:<width$}  {}", label, value, width = width);
+        }
+    }
+
+    if info.formats.is_empty() {
+        return;
+    }
+
+    println!();
+    let format_width = info
+        .formats
+        .iter()
+        .map(|format| format.format.len())
+        .max()
+        .unwrap_or(0)
+        .max("Format".len());
+    println!("{:<width$}  Bitrate", "Format", width = format_width);
+    for format in &info.formats {
+        println!(
+            "{:<width$}  {:>4} kbps{}",
+            format.format,
+            format.bitrate_kbps,
+            if format.selected {
+                "  (used by get)"
+            } else {
+                ""
+            },
+            width = format_width
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use std::{
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use anyhow::{Context, anyhow};
+use librespot::{
+    audio::{AudioDecrypt, AudioFile},
+    core::Session,
+    metadata::audio::{AudioFileFormat, AudioItem},
+};
+use tokio::process::Command;
+
+/// Spotify stores normalisation data at this offset in decrypted Ogg files
+const SPOTIFY_NORMALIZATION_OFFSET: u64 = 144;
+/// Loudness Spotify's normalisation gains bring tracks to
+const SPOTIFY_REFERENCE_LUFS: f64 = -14.0;
+/// ReplayGain 2.0 reference loudness
+const REPLAY_GAIN_REFERENCE_LUFS: f64 = -18.0;
+/// EBU R128 reference loudness used by Opus R128 tags
+const R128_REFERENCE_LUFS: f64 = -23.0;
+
+/// Where ReplayGain tags come from
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq)]
+pub enum ReplayGainMode {
+    /// Don't write ReplayGain tags
+    None,
+    /// Use normalisation data from Spotify's audio files
+    #[default]
+    Source,
+    /// Use Spotify's data or measure loudness with ffmpeg if it's missing
+    Analyze,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ReplayGain {
+    /// Gain to reach the ReplayGain reference of -18 LUFS
+    pub track_gain_db: f64,
+    /// Linear peak amplitude, 1.0 is full scale
+    pub track_peak: f64,
+    pub album_gain: Option<(f64, f64)>,
+}
+
+impl ReplayGain {
+    /// Read normalisation data from the header of a Spotify Ogg Vorbis file. Spotify's gains
+    /// aim for -14 LUFS, so they're lowered by 4 dB to match the ReplayGain reference.
+    pub async fn from_source(
+        session: &Session,
+        audio_item: &AudioItem,
+        format: AudioFileFormat,
+        bytes_per_second: usize,
+    ) -> anyhow::Result<Option<Self>> {
+        if !matches!(
+            format,
+            AudioFileFormat::OGG_VORBIS_96
+                | AudioFileFormat::OGG_VORBIS_160
+                | AudioFileFormat::OGG_VORBIS_320
+        ) {
+            return Ok(None);
+        }
+
+        let file_id = *audio_item
+            .files
+            .get(&format)
+            .ok_or_else(|| anyhow!("Missing file for {:?}", format))?;
+
+        let key = session
+            .audio_key()
+            .request(audio_item.track_id, file_id)
+            .await?;
+        let file = AudioFile::open(session, file_id, bytes_per_second).await?;
+
+        // Reading from an audio file blocks until the data arrives
+        let data = tokio::task::spawn_blocking(move || -> std::io::Result<[f32; 4]> {
+            let mut decrypted = AudioDecrypt::new(Some(key), file);
+            decrypted.seek(SeekFrom::Start(SPOTIFY_NORMALIZATION_OFFSET))?;
+
+            let mut values = [0f32; 4];
+            for value in &mut values {
+                let mut buf = [0u8; 4];
+                decrypted.read_exact(&mut buf)?;
+                *value = f32::from_le_bytes(buf);
+            }
+            Ok(values)
+        })
+        .await?
+        .context("Failed to read normalisation data")?;
+
+        let [track_gain_db, track_peak, album_gain_db, album_peak] = data.map(f64::from);
+        let to_replay_gain =
+            |gain_db: f64| gain_db + REPLAY_GAIN_REFERENCE_LUFS - SPOTIFY_REFERENCE_LUFS;
+
+        Ok(Some(Self {
+            track_gain_db: to_replay_gain(track_gain_db),
+            track_peak,
+            album_gain: Some((to_replay_gain(album_gain_db), album_peak)),
+        }))
+    }
+
+    /// Measure track loudness from raw PCM with ffmpeg's EBU R128 filter
+    pub async fn analyze(pcm_path: &Path) -> anyhow::Result<Self> {
+        let output = Command::new("ffmpeg")
+            .args(["-hide_banner", "-nostats", "-f", "s16le", "-ac", "2", "-i"])
+            .arg(pcm_path)
+            .args(["-af", "ebur128=peak=true", "-f", "null", "-"])
+            .output()
+            .await
+            .context("Failed to run ffmpeg")?;
+
+        // The summary is printed last, take the final values
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let find_value = |prefix: &str| {
+            stderr
+                .lines()
+                .rev()
+                .filter_map(|line| line.trim().strip_prefix(prefix))
+                .find_map(|rest| rest.split_whitespace().next()?.parse::<f64>().ok())
+        };
+
+        let loudness = find_value("I:").ok_or_else(|| anyhow!("ffmpeg didn't report loudness"))?;
+        let peak_dbfs = find_value("Peak:").unwrap_or(0.0);
+
+        Ok(Self {
+            track_gain_db: REPLAY_GAIN_REFERENCE_LUFS - loudness,
+            track_peak: 10f64.powf(peak_dbfs / 20.0),
+            album_gain: None,
+        })
+    }
+}
+
+/// Format a gain for ReplayGain tags
+pub(crate) fn format_gain(gain_db: f64) -> String {
+    format!("{:.2} dB", gain_db)
+}
+
+/// Format a peak for ReplayGain tags
+pub(crate) fn format_peak(peak: f64) -> String {
+    format!("{:.6}", peak)
+}
+
+/// Read a gain written by [`format_gain`]
+pub(crate) fn parse_gain(value: &str) -> Option<f64> {
+    value.trim().trim_end_matches("dB").trim().parse().ok()
+}
+
+/// Convert a ReplayGain value to an Opus R128 gain, in 1/256 dB relative to -23 LUFS
+pub(crate) fn r128_gain(gain_db: f64) -> String {
+    let gain = gain_db + (R128_REFERENCE_LUFS - REPLAY_GAIN_REFERENCE_LUFS);
+    ((gain * 256.0).round() as i16).to_string()
+}
+
+/// Convert an Opus R128 gain back to a ReplayGain value
+pub(crate) fn parse_r128_gain(value: &str) -> Option<f64> {
+    let gain = value.trim().parse::<i16>().ok()? as f64 / 256.0;
+    Some(gain - (R128_REFERENCE_LUFS - REPLAY_GAIN_REFERENCE_LUFS))
+}
@@ -0,0 +1,114 @@
+use std::path::Path;
+
+use anyhow::Context;
+use librespot::{
+    core::Session,
+    metadata::{
+        audio::{AudioItem, UniqueFields},
+        lyrics::{Lyrics, SyncType},
+    },
+};
+
+/// Where downloaded lyrics are saved
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq)]
+pub enum LyricsMode {
+    /// Don't download lyrics
+    #[default]
+    None,
+    /// Embed lyrics in the audio file
+    Embed,
+    /// Save synced lyrics to an .lrc file next to the audio file
+    Lrc,
+    /// Embed lyrics and save an .lrc file
+    Both,
+}
+
+impl LyricsMode {
+    pub fn embed(&self) -> bool {
+        matches!(self, LyricsMode::Embed | LyricsMode::Both)
+    }
+
+    pub fn lrc(&self) -> bool {
+        matches!(self, LyricsMode::Lrc | LyricsMode::Both)
+    }
+}
+
+pub(crate) struct TrackLyrics {
+    /// Start time of each line in milliseconds, if the lyrics are synced
+    lines: Vec<(Option<u32>, String)>,
+}
+
+impl TrackLyrics {
+    /// Get lyrics for a track, returns `None` for podcasts and tracks without lyrics
+    pub async fn fetch(session: &Session, audio_item: &AudioItem) -> Option<Self> {
+        if let UniqueFields::Episode { .. } = audio_item.unique_fields {
+            return None;
+        }
+
+        let lyrics = match Lyrics::get(session, &audio_item.track_id).await {
+            Ok(lyrics) => lyrics.lyrics,
+            Err(e) => {
+                log::info!("No lyrics for {}: {}", audio_item.name, e);
+                return None;
+            }
+        };
+
+        let synced = lyrics.sync_type == SyncType::LineSynced;
+
+        Some(Self {
+            lines: lyrics
+                .lines
+                .into_iter()
+                .map(|line| {
+                    let start = if synced {
+                        line.start_time_ms.parse().ok()
+                    } else {
+                        None
+                    };
+                    (start, line.words)
+                })
+                .collect(),
+        })
+    }
+
+    /// Lyrics without timestamps for embedding
+    pub fn to_plain_text(&self) -> String {
+        self.lines
+            .iter()
+            .map(|(_, words)| words.as_str())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Lyrics in LRC format, lines are left without timestamps if the lyrics aren't synced
+    pub fn to_lrc(&self, title: &str, artist: &str) -> String {
+        let mut lrc = format!("[ti:{}]\n[ar:{}]\n", title, artist);
+
+        for (start, words) in &self.lines {
+            if let Some(start) = start {
+                lrc += &format!(
+                    "[{:02}:{:02}.{:02}]",
+                    start / 60000,
+                    start / 1000 % 60,
+                    start % 1000 / 10
+                );
+            }
+            lrc += words;
+            lrc += "\n";
+        }
+
+        lrc
+    }
+
+    /// Save an .lrc file with the same name as the audio file
+    pub async fn write_lrc(
+        &self,
+        audio_path: &Path,
+        title: &str,
+        artist: &str,
+    ) -> anyhow::Result<()> {
+        tokio::fs::write(audio_path.with_extension("lrc"), self.to_lrc(title, artist))
+            .await
+            .context("Failed to write lyrics file")
+    }
+}
@@ -0,0 +1,49 @@
+/// An entry in an M3U file with what its `#EXTINF` line says about it
+pub(crate) struct M3uEntry {
+    /// Line number of the entry, from 1
+    pub line: usize,
+    /// File path, URL or Spotify URI
+    pub location: String,
+    pub duration_ms: Option<u32>,
+    /// Display name, usually `Artist - Title`
+    pub name: Option<String>,
+}
+
+/// Read the entries of an M3U or M3U8 file. `#PLAYLIST` sets the title.
+pub(crate) fn parse_m3u(text: &str) -> (Option<String>, Vec<M3uEntry>) {
+    let mut title = None;
+    let mut entries = vec![];
+    let mut extinf: Option<(Option<u32>, String)> = None;
+
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if let Some(name) = line.strip_prefix("#PLAYLIST:") {
+            title = Some(name.trim().to_owned());
+            continue;
+        }
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            let (duration, name) = info.split_once(',').unwrap_or((info, ""));
+            let duration = duration
+                .trim()
+                .parse::<i64>()
+                .ok()
+                .filter(|secs| *secs > 0)
+                .map(|secs| secs as u32 * 1000);
+            extinf = Some((duration, name.trim().to_owned()));
+            continue;
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (duration_ms, name) = extinf.take().unwrap_or_default();
+        entries.push(M3uEntry {
+            line: idx + 1,
+            location: line.to_owned(),
+            duration_ms,
+            name: Some(name).filter(|name| !name.is_empty()),
+        });
+    }
+
+    (title, entries)
+}
@@ -1,30 +1,53 @@
-use std::{
-    fs::File,
-    io::{Read, Write},
-    path::{Path, PathBuf},
-};
+use std::path::{Path, PathBuf};
 
 use anyhow::bail;
+use chrono::NaiveDate;
 use clap::{Parser, Subcommand, command};
 use librespot::{
     core::{
         Session, SessionConfig, SpotifyId, cache::Cache, error::ErrorKind,
         spotify_id::SpotifyItemType,
     },
-    metadata::{Album, Metadata, Playlist, Show, audio::AudioItem},
+    metadata::audio::AudioItem,
 };
 use regex::Regex;
 
 use crate::{
+    cleanup::{CLEANUP, Cleanup},
     core::{Loader, OutputFormat},
-    metadata::get_file_name,
-    playlist::{SavedPlaylist, SavedTrack},
+    edit::SortKey,
+    export::{ExportEntry, PlaylistFormat, write_playlists},
+    loudness::ReplayGainMode,
+    lyrics::LyricsMode,
+    metadata::{DEFAULT_DATE_FORMAT, get_file_name, is_valid_date_format},
+    normalize::{CaseFolding, NameStyle, Normalization},
+    playlist::{SavedPlaylist, SavedTrack, fetch_source},
+    podcast::ShowNotesMode,
+    search::SearchType,
+    tags::ArtistTags,
 };
 
 mod auth;
+mod cleanup;
 mod core;
+mod edit;
+mod export;
+mod feed;
+mod import;
+mod info;
+mod loudness;
+mod lyrics;
+mod m3u;
 mod metadata;
+mod normalize;
 mod playlist;
+mod podcast;
+mod refresh;
+mod rename;
+mod retag;
+mod search;
+mod tags;
+mod util;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -42,6 +65,34 @@ struct CommonArgs {
     output_path: Option<PathBuf>,
 }
 
+#[derive(Parser)]
+struct CleanupArgs {
+    /// Cleanup rule applied to names, can be repeated. Rules run in order
+    /// and look like [FIELD:]PATTERN[=>REPLACEMENT]:
+    /// FIELD - name (default for regexes), title (default for presets) or artist
+    /// PATTERN - a regex or one of the presets:
+    ///   @feat-tags, @parentheses, @brackets, @remaster
+    /// REPLACEMENT - text to replace matches with, removes them if empty
+    #[arg(
+        short = 'r',
+        long = "cleanup",
+        alias = "cleanup-regex",
+        verbatim_doc_comment
+    )]
+    cleanup_rules: Vec<String>,
+}
+
+#[derive(Parser)]
+struct DetailsArgs {
+    /// Write each track as a table with its file name, artists, album and duration
+    #[arg(long, default_value_t = false)]
+    details: bool,
+
+    /// Format for the file names written by `--details`, supports the same arguments as `get`
+    #[arg(long)]
+    name_format: Option<String>,
+}
+
 #[derive(Parser)]
 struct DownloadArgs {
     #[clap(flatten)]
@@ -52,27 +103,104 @@ struct DownloadArgs {
     format: Option<OutputFormat>,
 
     /// Format used for file names. Supports these arguments:
-    /// %a - main artist name
+    /// %a - main artist name, or show name for podcasts
     /// %A - all artist names separated with commas
     /// %t - track title
     /// %n - track number
-    #[arg(short, long, verbatim_doc_comment, default_value = "%a - %t")]
-    name_format: String,
+    /// %D - duration
+    /// %d - episode publish date
+    /// %e - episode number
+    /// Defaults to "%a - %t" for tracks and "%d - %t" for podcasts
+    #[arg(short, long, verbatim_doc_comment)]
+    name_format: Option<String>,
+
+    /// Format for episode publish dates, uses strftime syntax
+    #[arg(long, default_value = DEFAULT_DATE_FORMAT)]
+    date_format: String,
+
+    #[clap(flatten)]
+    cleanup_args: CleanupArgs,
+
+    /// Apply title and artist cleanup rules to embedded tags
+    #[arg(long, default_value_t = false)]
+    clean_tags: bool,
+
+    #[clap(flatten)]
+    artist_tags: ArtistTags,
+
+    /// Download lyrics for tracks
+    #[arg(long, value_enum, default_value_t = LyricsMode::None)]
+    lyrics: LyricsMode,
+
+    /// Source for ReplayGain tags
+    #[arg(long, value_enum, default_value_t = ReplayGainMode::Source)]
+    replaygain: ReplayGainMode,
+
+    /// Save podcast show notes next to each episode
+    #[arg(long, value_enum, default_value_t = ShowNotesMode::None)]
+    show_notes: ShowNotesMode,
+
+    /// Unicode normalization form for file names
+    #[arg(long, value_enum, default_value_t = Normalization::None)]
+    normalize: Normalization,
+
+    /// Replace non-ASCII characters in file names with ASCII lookalikes
+    #[arg(long, default_value_t = false)]
+    ascii: bool,
 
-    /// Any characters captured by this regex will be removed
-    /// from the file name
-    #[arg(short = 'r', long)]
-    cleanup_regex: Option<String>,
+    /// Change the case of file names
+    #[arg(long = "case", value_enum, default_value_t = CaseFolding::Keep)]
+    case_folding: CaseFolding,
 
     /// Always download tracks even if they already exist
     #[arg(long = "force", default_value_t = false)]
     force_download: bool,
 
+    /// Print output paths and formats without downloading anything
+    #[arg(long, default_value_t = false)]
+    dry_run: bool,
+
+    /// Write a playlist file in this format next to playlist and album downloads, can be
+    /// repeated
+    #[arg(long, value_enum)]
+    export: Vec<PlaylistFormat>,
+
+    /// Write a podcast RSS feed into the folder after downloading a show
+    #[arg(long, default_value_t = false, requires = "base_url")]
+    rss: bool,
+
+    /// URL the show folder is served from, needed for links in the RSS feed
+    #[arg(long)]
+    base_url: Option<String>,
+
+    /// Only download the N most recent episodes of a show
+    #[arg(long)]
+    latest: Option<usize>,
+
+    /// Only download episodes published on or after this date (YYYY-MM-DD)
+    #[arg(long)]
+    since: Option<NaiveDate>,
+
+    /// Stop at the first episode that is already in the show folder
+    #[arg(long, default_value_t = false)]
+    new_only: bool,
+
     /// Maximum number of retries for failed requests
     #[arg(long, default_value_t = 3)]
     max_tries: u32,
 }
 
+impl DownloadArgs {
+    fn name_style(&self) -> NameStyle {
+        NameStyle {
+            normalization: self.normalize,
+            ascii: self.ascii,
+            case: self.case_folding,
+            date_format: self.date_format.clone(),
+        }
+    }
+}
+
 #[derive(Parser)]
 struct SaveArgs {
     #[clap(flatten)]
@@ -81,6 +209,201 @@ struct SaveArgs {
     /// Saved playlist name
     #[arg(short, long)]
     name: Option<String>,
+
+    #[clap(flatten)]
+    details_args: DetailsArgs,
+}
+
+#[derive(Parser)]
+struct ImportArgs {
+    /// M3U, CSV or JSON playlist to import
+    file: PathBuf,
+
+    /// TOML file to write
+    output: PathBuf,
+
+    /// Playlist title, defaults to the name in the file or the file name
+    #[arg(short, long)]
+    title: Option<String>,
+
+    /// How close a search result has to be to count as a match, from 0 to 1
+    #[arg(long, default_value_t = 0.75)]
+    min_score: f64,
+
+    #[clap(flatten)]
+    details_args: DetailsArgs,
+}
+
+#[derive(Parser)]
+struct SearchArgs {
+    /// What to search for
+    query: String,
+
+    /// Type of item to find
+    #[arg(short = 't', long = "type", value_enum, default_value = "track")]
+    search_type: SearchType,
+
+    /// Number of results
+    #[arg(short, long, default_value_t = 10)]
+    limit: u32,
+
+    /// Pick a result to download or add to a playlist
+    #[arg(short, long, default_value_t = false)]
+    interactive: bool,
+
+    /// Location for downloads started from `--interactive`
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// TOML playlist to add results to in `--interactive`
+    #[arg(short, long)]
+    playlist: Option<PathBuf>,
+}
+
+#[derive(Parser)]
+struct InfoArgs {
+    /// Share link or Spotify URI
+    url: String,
+
+    /// Print JSON instead of a table
+    #[arg(long, default_value_t = false)]
+    json: bool,
+}
+
+#[derive(Parser)]
+struct RenameArgs {
+    /// Folder with downloaded files
+    folder: PathBuf,
+
+    /// Format used for new file names, supports the same arguments as `get`.
+    /// %n is the position on the album counting all discs, like an album download
+    #[arg(short, long)]
+    name_format: Option<String>,
+
+    /// Replace the tags with fresh metadata from Spotify
+    #[arg(long, default_value_t = false)]
+    retag: bool,
+
+    /// Print the new names without renaming anything
+    #[arg(long, default_value_t = false)]
+    dry_run: bool,
+
+    /// Revert the last rename in this folder
+    #[arg(long, default_value_t = false)]
+    undo: bool,
+}
+
+#[derive(Parser)]
+struct RetagArgs {
+    /// Folder with downloaded files
+    folder: PathBuf,
+
+    #[clap(flatten)]
+    artist_tags: ArtistTags,
+
+    #[clap(flatten)]
+    cleanup_args: CleanupArgs,
+}
+
+#[derive(Parser)]
+struct FeedArgs {
+    /// Folder with downloaded episodes
+    folder: PathBuf,
+
+    /// URL the folder is served from, RSS needs full links to the episodes
+    #[arg(long)]
+    base_url: String,
+}
+
+#[derive(Parser)]
+struct CheckArgs {
+    /// TOML playlist to check
+    file: PathBuf,
+
+    /// Update files from older daytrip versions to the current format
+    #[arg(long, default_value_t = false)]
+    migrate: bool,
+}
+
+#[derive(Parser)]
+struct RefreshArgs {
+    /// TOML playlist saved with `daytrip save`
+    file: PathBuf,
+
+    #[clap(flatten)]
+    details_args: DetailsArgs,
+
+    /// Print the changes without updating the file
+    #[arg(long, default_value_t = false)]
+    dry_run: bool,
+}
+
+#[derive(Parser)]
+struct MergeArgs {
+    /// Playlists to combine, the title and defaults come from the first one
+    #[arg(required = true, num_args = 2..)]
+    files: Vec<PathBuf>,
+
+    /// File to write the merged playlist to
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Title for the merged playlist
+    #[arg(long)]
+    title: Option<String>,
+}
+
+#[derive(Parser)]
+struct DiffArgs {
+    /// TOML playlist
+    file: PathBuf,
+
+    /// Another TOML playlist or a Spotify URL to compare with
+    other: String,
+}
+
+#[derive(Parser)]
+struct DedupeArgs {
+    /// TOML playlist
+    file: PathBuf,
+
+    /// Write the result here instead of changing the file
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Parser)]
+struct SortArgs {
+    /// TOML playlist
+    file: PathBuf,
+
+    /// What to sort by
+    #[arg(long, value_enum)]
+    by: SortKey,
+
+    /// Sort in reverse order
+    #[arg(long, default_value_t = false)]
+    reverse: bool,
+
+    /// Seed for `--by shuffle` to get the same order again
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Write the result here instead of changing the file
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum PlaylistCommand {
+    /// Combine several playlists into one, skipping tracks that are already in it
+    Merge(MergeArgs),
+    /// Show tracks that are only in one of two playlists
+    Diff(DiffArgs),
+    /// Remove tracks that are in a playlist more than once
+    Dedupe(DedupeArgs),
+    /// Sort or shuffle a playlist
+    Sort(SortArgs),
 }
 
 #[derive(Subcommand)]
@@ -89,6 +412,29 @@ enum Commands {
     Get(DownloadArgs),
     /// Save an item to a TOML playlist
     Save(SaveArgs),
+    /// Make a TOML playlist from an M3U, CSV or JSON playlist
+    Import(ImportArgs),
+    /// Find tracks, albums, artists or shows on Spotify
+    Search(SearchArgs),
+    /// Show details and available audio formats for an item
+    Info(InfoArgs),
+    /// Print the files `get` would create without downloading them
+    Preview(DownloadArgs),
+    /// Rename downloaded files to a new name format
+    Rename(RenameArgs),
+    /// Replace tags in downloaded files with fresh metadata
+    Retag(RetagArgs),
+    /// Write a podcast RSS feed for a folder of downloaded episodes
+    Feed(FeedArgs),
+    /// Check a TOML playlist for mistakes
+    Check(CheckArgs),
+    /// Update a TOML playlist with changes from its Spotify source
+    Refresh(RefreshArgs),
+    /// Edit TOML playlists
+    Playlist {
+        #[command(subcommand)]
+        command: PlaylistCommand,
+    },
 }
 
 fn parse_item_type(item_type: &str) -> SpotifyItemType {
@@ -105,11 +451,58 @@ fn parse_item_type(item_type: &str) -> SpotifyItemType {
     }
 }
 
+/// Get the Spotify item from a share link, URI or bare track ID
+pub(crate) fn parse_item_ref(url: &str) -> anyhow::Result<SpotifyId> {
+    if url.starts_with("spotify:") {
+        let Ok(item_ref) = SpotifyId::from_uri(url) else {
+            bail!("Invalid Spotify ID: {}", url);
+        };
+        return Ok(item_ref);
+    }
+
+    let re = Regex::new(r"spotify\.com/(\w+)/(\w+)").unwrap();
+    if let Some(res) = re.captures(url) {
+        let item_type = &res[1];
+        let id = &res[2];
+        let Ok(mut item_ref) = SpotifyId::from_base62(id) else {
+            bail!("Invalid Spotify ID: {}", id);
+        };
+        item_ref.item_type = parse_item_type(item_type);
+        Ok(item_ref)
+    } else {
+        let Ok(mut item_ref) = SpotifyId::from_base62(url) else {
+            bail!("Not a Spotify link, URI or ID: {}", url);
+        };
+        item_ref.item_type = SpotifyItemType::Track;
+        Ok(item_ref)
+    }
+}
+
+/// Whether an argument to `get` is a playlist file rather than a Spotify item. Anything
+/// that looks like a path counts, so a missing file isn't reported as a bad link. URLs
+/// never do, so a mistyped link gets a link error.
+fn is_playlist_path(arg: &str) -> bool {
+    if arg.starts_with("spotify:")
+        || arg.contains("spotify.com/")
+        || arg.starts_with("http://")
+        || arg.starts_with("https://")
+    {
+        return false;
+    }
+    let path = Path::new(arg);
+    path.is_file() || path.extension().is_some() || arg.contains(['/', '\\'])
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     env_logger::init();
     let args = Cli::parse();
 
+    // Checking a playlist doesn't need Spotify
+    if let Commands::Check(cmd) = args.command {
+        return playlist::check(cmd);
+    }
+
     let cache = match Cache::new(
         Some("./daytrip-cache"),
         Some("./daytrip-cache"),
@@ -167,9 +560,39 @@ async fn main() -> anyhow::Result<()> {
         Commands::Get(cmd) => {
             download(&loader, cmd).await?;
         }
+        Commands::Preview(mut cmd) => {
+            cmd.dry_run = true;
+            download(&loader, cmd).await?;
+        }
         Commands::Save(cmd) => {
             save_to_file(&loader, cmd).await?;
         }
+        Commands::Import(cmd) => {
+            import::import(&loader, cmd).await?;
+        }
+        Commands::Search(cmd) => {
+            search::run(&loader, cmd).await?;
+        }
+        Commands::Info(cmd) => {
+            info::info(&loader, cmd).await?;
+        }
+        Commands::Rename(cmd) => {
+            rename::rename(&loader, cmd).await?;
+        }
+        Commands::Retag(cmd) => {
+            _ = CLEANUP.set(Cleanup::new(&cmd.cleanup_args.cleanup_rules, true)?);
+            retag::retag(&loader, cmd).await?;
+        }
+        Commands::Feed(cmd) => {
+            feed::feed(&loader, cmd).await?;
+        }
+        Commands::Refresh(cmd) => {
+            refresh::refresh(&loader, cmd).await?;
+        }
+        Commands::Playlist { command } => {
+            edit::run(&loader, command).await?;
+        }
+        Commands::Check(_) => unreachable!(),
     }
 
     println!("All set!");
@@ -179,185 +602,159 @@ async fn main() -> anyhow::Result<()> {
 
 async fn download(loader: &Loader, cmd: DownloadArgs) -> anyhow::Result<()> {
     let path = Path::new(&cmd.common_args.url);
-    match File::open(path) {
-        Ok(mut file) => {
-            let mut buf = String::new();
-            file.read_to_string(&mut buf)?;
-            let plist: SavedPlaylist = toml::from_str(&buf)?;
-
-            let folder_path = match cmd.common_args.output_path {
-                Some(path) => path,
-                None => PathBuf::from(&plist.title),
-            };
+    let saved_playlist = if is_playlist_path(&cmd.common_args.url) {
+        Some(SavedPlaylist::load(path)?.with_includes(path)?)
+    } else {
+        None
+    };
 
-            let format = cmd.format.unwrap_or(OutputFormat::Opus);
-            let extension = format.extension();
+    // Saved playlists can bring their own cleanup rules
+    let mut cleanup_rules = cmd.cleanup_args.cleanup_rules.clone();
+    if let Some(plist) = &saved_playlist {
+        cleanup_rules.extend(plist.cleanup.iter().cloned());
+    }
+    let cleanup = Cleanup::new(&cleanup_rules, cmd.clean_tags)?;
+    _ = CLEANUP.set(cleanup);
+    if !is_valid_date_format(&cmd.date_format) {
+        bail!("Invalid date format: {}", cmd.date_format);
+    }
+    let name_style = cmd.name_style();
+
+    let dry_run = cmd.dry_run;
+
+    match saved_playlist {
+        Some(plist) => {
+            // Command line options win over the playlist defaults. `output` is relative to
+            // the playlist file, like `include`.
+            let folder_path = cmd
+                .common_args
+                .output_path
+                .clone()
+                .or_else(|| {
+                    let output = plist.output.as_ref()?;
+                    Some(path.parent().unwrap_or(Path::new("")).join(output))
+                })
+                .unwrap_or_else(|| PathBuf::from(&plist.title));
+            let name_format = cmd.name_format.as_deref().or(plist.name_format.as_deref());
 
             let mut idx = 1;
+            let mut entries = vec![];
 
             for track in &plist.tracks {
-                if let Ok(id) = track.id() {
-                    let session = loader.get_session();
+                let overrides = track.overrides();
+
+                let id = match track.id() {
+                    Ok(id) if !overrides.skip => id,
+                    _ => {
+                        idx += 1;
+                        continue;
+                    }
+                };
+
+                // Albums, playlists and shows are looked up now so they're always current
+                let session = loader.get_session();
+                let ids = if track.is_collection() {
+                    fetch_source(session, id).await?.1
+                } else {
+                    vec![id]
+                };
+
+                for id in ids {
                     let audio_item = AudioItem::get_file(session, id).await?;
 
+                    let format = overrides
+                        .format
+                        .or(cmd.format)
+                        .or(overrides.included_format)
+                        .or(plist.format)
+                        .unwrap_or(OutputFormat::Opus);
+                    let extension = format.extension();
+
                     let file_name = match track.name() {
-                        Some(name) => name.to_owned() + "." + extension,
+                        Some(name) => name_style.apply(name) + "." + extension,
                         None => {
-                            get_file_name(&audio_item, &cmd.name_format, Some(idx), Some(extension))
-                                .await
+                            get_file_name(
+                                session,
+                                &audio_item,
+                                name_format,
+                                Some(idx),
+                                Some(extension),
+                                &name_style,
+                            )
+                            .await
                         }
                     };
 
+                    let folder = match &overrides.folder {
+                        Some(folder) => folder_path.join(folder),
+                        None => folder_path.clone(),
+                    };
+                    let path = folder.join(&file_name);
                     loader
                         .download_track_with_retry(
                             &audio_item,
-                            folder_path.join(&file_name).as_path(),
+                            path.as_path(),
                             format,
-                            cmd.force_download,
-                            cmd.max_tries,
+                            &cmd,
+                            overrides,
                         )
                         .await?;
+                    entries.push(ExportEntry::new(&audio_item, &path, &folder_path));
+
+                    idx += 1;
                 }
+            }
 
-                idx += 1;
+            if !dry_run {
+                write_playlists(&folder_path, &plist.title, &entries, &cmd.export).await?;
             }
         }
-        Err(_) => {
-            let item_ref = if cmd.common_args.url.starts_with("spotify:") {
-                let Ok(item_ref) = SpotifyId::from_base62(&cmd.common_args.url) else {
-                    bail!("Invalid Spotify ID: {}", &cmd.common_args.url);
-                };
-                item_ref
-            } else {
-                let re = Regex::new(r"spotify\.com/(\w+)/(\w+)").unwrap();
-                if let Some(res) = re.captures(&cmd.common_args.url) {
-                    let item_type = &res[1];
-                    let id = &res[2];
-                    let Ok(mut item_ref) = SpotifyId::from_base62(id) else {
-                        bail!("Invalid Spotify ID: {}", id);
-                    };
-                    item_ref.item_type = parse_item_type(item_type);
-                    item_ref
-                } else {
-                    let Ok(mut item_ref) = SpotifyId::from_base62(&cmd.common_args.url) else {
-                        bail!("Invalid Spotify ID: {}", &cmd.common_args.url);
-                    };
-                    item_ref.item_type = SpotifyItemType::Track;
-                    item_ref
-                }
-            };
+        None => {
+            let item_ref = parse_item_ref(&cmd.common_args.url)?;
             loader.download(item_ref, cmd).await;
         }
     };
 
-    tokio::fs::remove_file("temp.pcm").await?;
+    // Nothing is decoded when every track is skipped, so there may be no temp file
+    if !dry_run
+        && let Err(e) = tokio::fs::remove_file("temp.pcm").await
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        return Err(e.into());
+    }
 
     Ok(())
 }
 
 async fn save_to_file(loader: &Loader, cmd: SaveArgs) -> anyhow::Result<()> {
-    let item_ref = if cmd.common_args.url.starts_with("spotify:") {
-        let Ok(item_ref) = SpotifyId::from_base62(&cmd.common_args.url) else {
-            bail!("Invalid Spotify ID: {}", &cmd.common_args.url);
-        };
-        item_ref
-    } else {
-        let re = Regex::new(r"spotify\.com/(\w+)/(\w+)").unwrap();
-        if let Some(res) = re.captures(&cmd.common_args.url) {
-            let item_type = &res[1];
-            let id = &res[2];
-            let Ok(mut item_ref) = SpotifyId::from_base62(id) else {
-                bail!("Invalid Spotify ID: {}", id);
-            };
-            item_ref.item_type = parse_item_type(item_type);
-            item_ref
-        } else {
-            let Ok(mut item_ref) = SpotifyId::from_base62(&cmd.common_args.url) else {
-                bail!("Invalid Spotify ID: {}", &cmd.common_args.url);
-            };
-            item_ref.item_type = SpotifyItemType::Track;
-            item_ref
-        }
-    };
-
-    let title: String;
-
-    let tracks = match item_ref.item_type {
-        SpotifyItemType::Album => {
-            let session = loader.get_session();
-            let plist = Album::get(session, &item_ref).await?;
-            title = cmd.name.unwrap_or(plist.name.to_owned());
-            plist
-                .tracks()
-                .filter_map(|id| match id.to_uri() {
-                    Ok(id) => Some(SavedTrack::Id(id)),
-                    Err(err) => {
-                        log::error!("Failed to get track URI: {}", err);
-                        None
-                    }
-                })
-                .collect::<Vec<_>>()
-        }
-        SpotifyItemType::Episode => {
-            let session = loader.get_session();
-            let audio_item = AudioItem::get_file(session, item_ref).await?;
-            title = cmd.name.unwrap_or(audio_item.name);
-            vec![SavedTrack::Id(audio_item.uri)]
-        }
-        SpotifyItemType::Playlist => {
-            let session = loader.get_session();
-            let plist = Playlist::get(session, &item_ref).await?;
-            title = cmd.name.unwrap_or(plist.name().to_owned());
-            plist
-                .tracks()
-                .filter_map(|id| match id.to_uri() {
-                    Ok(id) => Some(SavedTrack::Id(id)),
-                    Err(err) => {
-                        log::error!("Failed to get track URI: {}", err);
-                        None
-                    }
-                })
-                .collect::<Vec<_>>()
-        }
-        SpotifyItemType::Show => {
-            let session = loader.get_session();
-            let plist = Show::get(session, &item_ref).await?;
-            title = cmd.name.unwrap_or(plist.name.to_owned());
-            plist
-                .episodes
-                .iter()
-                .filter_map(|id| match id.to_uri() {
-                    Ok(id) => Some(SavedTrack::Id(id)),
-                    Err(err) => {
-                        log::error!("Failed to get track URI: {}", err);
-                        None
-                    }
-                })
-                .collect::<Vec<_>>()
-        }
-        SpotifyItemType::Track => {
-            let session = loader.get_session();
-            let audio_item = AudioItem::get_file(session, item_ref).await?;
-            title = cmd.name.unwrap_or(audio_item.name);
-            vec![SavedTrack::Id(audio_item.uri)]
-        }
-        _ => bail!("Unsupported item type: {:?}", item_ref.item_type),
-    };
-
-    let mut file = File::create(
-        cmd.common_args
-            .output_path
-            .unwrap_or(PathBuf::from(&format!(
-                "{}.toml",
-                &metadata::legalize_name(&title)
-            ))),
-    )?;
-
-    let plist = SavedPlaylist { title, tracks };
-
-    let serialized = toml::to_string_pretty(&plist)?;
-
-    file.write(serialized.as_bytes())?;
+    let item_ref = parse_item_ref(&cmd.common_args.url)?;
+
+    let session = loader.get_session();
+    let (name, ids) = fetch_source(session, item_ref).await?;
+    let title = cmd.name.unwrap_or(name);
+
+    let mut tracks = vec![];
+    for (idx, id) in ids.iter().enumerate() {
+        let track = SavedTrack::fetch(
+            session,
+            *id,
+            idx as u32 + 1,
+            cmd.details_args.details,
+            cmd.details_args.name_format.as_deref(),
+        )
+        .await?;
+        tracks.extend(track);
+    }
 
-    Ok(())
+    let path = cmd
+        .common_args
+        .output_path
+        .unwrap_or(PathBuf::from(&format!(
+            "{}.toml",
+            &metadata::legalize_name(&title)
+        )));
+
+    let mut plist = SavedPlaylist::new(title, tracks);
+    plist.source = item_ref.to_uri().ok();
+    plist.save(&path)
 }
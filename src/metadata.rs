@@ -1,17 +1,30 @@
 use std::path::Path;
 
+use chrono::{
+    DateTime,
+    format::{Item, StrftimeItems},
+};
 use itertools::Itertools;
-use once_cell::sync::OnceCell;
 
 use librespot::{
-    metadata::audio::{AudioFileFormat, AudioItem, UniqueFields},
+    core::Session,
+    metadata::{
+        Episode, Metadata,
+        audio::{AudioFileFormat, AudioItem, UniqueFields},
+    },
     playback::config::{Bitrate, PlayerConfig},
 };
-use regex::Regex;
 
-use crate::OutputFormat;
+use crate::{
+    OutputFormat,
+    cleanup::{CleanupField, clean},
+    normalize::NameStyle,
+};
 
-pub(crate) static REGEX_FILTER: OnceCell<Regex> = OnceCell::new();
+pub(crate) const DEFAULT_TRACK_FORMAT: &str = "%a - %t";
+/// Episodes start with the publish date so they sort chronologically
+pub(crate) const DEFAULT_EPISODE_FORMAT: &str = "%d - %t";
+pub(crate) const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
 
 pub fn get_input_format(config: &PlayerConfig, audio_item: &AudioItem) -> Option<AudioFileFormat> {
     let formats = match config.bitrate {
@@ -66,55 +79,100 @@ pub(crate) fn legalize_name(name: &str) -> String {
     name.replace(&['/', '\\', ':', '*', '?', '"', '<', '>', '|'], "_")
 }
 
+/// Format a duration like `4m05s` or `1h02m30s`
+pub(crate) fn format_duration(duration_ms: u32) -> String {
+    let seconds = duration_ms / 1000;
+    match seconds / 3600 {
+        0 => format!("{}m{:02}s", seconds / 60, seconds % 60),
+        hours => format!("{}h{:02}m{:02}s", hours, seconds / 60 % 60, seconds % 60),
+    }
+}
+
+/// Check if `format` is a valid strftime format for episode dates
+pub(crate) fn is_valid_date_format(format: &str) -> bool {
+    !StrftimeItems::new(format).any(|item| item == Item::Error)
+}
+
 pub async fn get_file_name(
+    session: &Session,
     audio_item: &AudioItem,
-    template: &str,
+    template: Option<&str>,
     track_number: Option<u32>,
     extension: Option<&str>,
+    style: &NameStyle,
 ) -> String {
+    let title = legalize_name(&style.apply(&clean(CleanupField::Title, &audio_item.name)));
+    // Normalizing can turn characters like `／` into path separators, so names are made
+    // legal after it
+    let artist = |name: &str| legalize_name(&style.apply(&clean(CleanupField::Artist, name)));
+
     let name = match &audio_item.unique_fields {
         UniqueFields::Track { artists, .. } => {
             // music
-            let title = template
+            template
+                .unwrap_or(DEFAULT_TRACK_FORMAT)
                 .replace(
                     "%a",
                     &artists
                         .first()
-                        .map(|artist| artist.name.to_owned())
+                        .map(|a| artist(&a.name))
                         .unwrap_or("".to_owned()),
                 )
-                .replace("%A", &artists.iter().map(|artist| &*artist.name).join(", "))
-                .replace("%t", &legalize_name(&audio_item.name))
-                .replace("%n", &format!("{:02}", track_number.unwrap_or(0)));
-
-            match extension {
-                Some(ext) => format!("{}.{}", title, ext),
-                None => title,
-            }
+                .replace("%A", &artists.iter().map(|a| artist(&a.name)).join(", "))
+                .replace("%t", &title)
+                .replace("%n", &format!("{:02}", track_number.unwrap_or(0)))
+                .replace("%D", &format_duration(audio_item.duration_ms))
         }
-        UniqueFields::Episode { show_name, .. } => {
+        UniqueFields::Episode {
+            show_name,
+            publish_time,
+            ..
+        } => {
             // podcast
+            let template = template.unwrap_or(DEFAULT_EPISODE_FORMAT);
 
-            let title = template
-                .replace("%a", &show_name)
-                .replace("%t", &legalize_name(&audio_item.name))
-                .replace("%n", &format!("{:02}", track_number.unwrap_or(0)));
+            let date = DateTime::from_timestamp_millis(publish_time.as_timestamp_ms())
+                .map(|date| date.format(&style.date_format).to_string())
+                .unwrap_or_default();
 
-            match extension {
-                Some(ext) => format!("{}.{}", title, ext),
-                None => title,
-            }
+            // Episode numbers aren't part of the audio item, only look them up when needed
+            let episode_number = if template.contains("%e") {
+                match Episode::get(session, &audio_item.track_id).await {
+                    Ok(episode) if episode.number > 0 => format!("{:02}", episode.number),
+                    Ok(_) => String::new(),
+                    Err(e) => {
+                        log::warn!(
+                            "Failed to get episode number for {}: {}",
+                            audio_item.name,
+                            e
+                        );
+                        String::new()
+                    }
+                }
+            } else {
+                String::new()
+            };
+
+            template
+                .replace("%a", &artist(show_name))
+                .replace("%t", &title)
+                .replace("%n", &format!("{:02}", track_number.unwrap_or(0)))
+                .replace("%d", &legalize_name(&date))
+                .replace("%e", &episode_number)
+                .replace("%D", &format_duration(audio_item.duration_ms))
         }
     };
 
-    if let Some(regex) = REGEX_FILTER.get() {
-        regex.replace_all(&name, "").into_owned()
-    } else {
-        name
+    let name = clean(CleanupField::Name, &name);
+
+    match extension {
+        Some(ext) => format!("{}.{}", name, ext),
+        None => name,
     }
 }
 
-pub fn try_get_format_from_file_name(name: &str) -> Option<OutputFormat> {
+pub fn try_get_format_from_file_name(name: Option<&str>) -> Option<OutputFormat> {
+    let name = name?;
     let extension = name.split('.').last()?;
     OutputFormat::from_extension(extension)
 }
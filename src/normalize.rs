@@ -0,0 +1,69 @@
+use unicode_normalization::UnicodeNormalization;
+
+use crate::metadata::DEFAULT_DATE_FORMAT;
+
+/// Unicode normalization form used for file names
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq)]
+pub enum Normalization {
+    /// Keep names as Spotify returns them
+    #[default]
+    None,
+    Nfc,
+    Nfd,
+    Nfkc,
+}
+
+/// Case conversion used for file names
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq)]
+pub enum CaseFolding {
+    #[default]
+    Keep,
+    Lower,
+    Upper,
+}
+
+/// How file names are written
+#[derive(Clone, Debug)]
+pub(crate) struct NameStyle {
+    pub normalization: Normalization,
+    /// Replace non-ASCII characters with their closest ASCII equivalent
+    pub ascii: bool,
+    pub case: CaseFolding,
+    /// strftime format for `%d`
+    pub date_format: String,
+}
+
+impl Default for NameStyle {
+    fn default() -> Self {
+        Self {
+            normalization: Normalization::default(),
+            ascii: false,
+            case: CaseFolding::default(),
+            date_format: DEFAULT_DATE_FORMAT.to_owned(),
+        }
+    }
+}
+
+impl NameStyle {
+    /// Apply the style to a file name. Tags are never changed.
+    pub fn apply(&self, name: &str) -> String {
+        let name: String = match self.normalization {
+            Normalization::None => name.to_owned(),
+            Normalization::Nfc => name.nfc().collect(),
+            Normalization::Nfd => name.nfd().collect(),
+            Normalization::Nfkc => name.nfkc().collect(),
+        };
+
+        let name = if self.ascii {
+            deunicode::deunicode(&name)
+        } else {
+            name
+        };
+
+        match self.case {
+            CaseFolding::Keep => name,
+            CaseFolding::Lower => name.to_lowercase(),
+            CaseFolding::Upper => name.to_uppercase(),
+        }
+    }
+}
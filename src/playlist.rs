@@ -1,14 +1,288 @@
-use librespot::core::{Error as SpotifyError, SpotifyId};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Context, bail};
+use itertools::Itertools;
+use librespot::{
+    core::{Error as SpotifyError, Session, SpotifyId, spotify_id::SpotifyItemType},
+    metadata::{
+        Album, Metadata, Playlist, Show,
+        audio::{AudioItem, UniqueFields},
+    },
+};
+use once_cell::sync::Lazy;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use toml::{Spanned, Value};
 
-#[derive(Serialize, Deserialize)]
+use crate::{
+    CheckArgs,
+    core::OutputFormat,
+    m3u::parse_m3u,
+    metadata::{format_duration, get_file_name},
+    normalize::NameStyle,
+    parse_item_ref,
+    tags::Tags,
+};
+
+/// Version written to new playlist files:
+/// 1. Files without a version, only `title` and `tracks` with `id` and `name`
+/// 2. `version`, the `format`, `name_format`, `output` and `cleanup` defaults and track
+///    overrides. The `info` table of `save --details` was added to version 2 later
+///    without a new number, downloads ignore it.
+/// 3. `source` and `removed`
+/// 4. `include` and album, playlist and show entries
+pub(crate) const PLAYLIST_VERSION: u32 = 4;
+
+static VERSION_LINE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?m)^version\s*=\s*\d+").unwrap());
+
+/// Settings for a single track that replace the playlist and command line ones
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TrackOverrides {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<OutputFormat>,
+    /// Folder relative to the playlist output folder
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub folder: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub album: Option<String>,
+    /// Seconds to cut from the start
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start: Option<f64>,
+    /// Position in seconds where the track is cut off
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<f64>,
+    /// Leave this track out when downloading
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub skip: bool,
+    /// Default format of the included file the track came from, the command line wins
+    /// over it like it does over the playlist defaults
+    #[serde(skip)]
+    pub included_format: Option<OutputFormat>,
+}
+
+static NO_OVERRIDES: TrackOverrides = TrackOverrides {
+    format: None,
+    folder: None,
+    title: None,
+    artist: None,
+    album: None,
+    start: None,
+    end: None,
+    skip: false,
+    included_format: None,
+};
+
+impl TrackOverrides {
+    /// Replace fetched tag values with the ones set in the playlist
+    pub fn apply(&self, tags: &mut Tags) {
+        if let Some(title) = &self.title {
+            tags.title = title.clone();
+        }
+        if let Some(artist) = &self.artist {
+            tags.artists = vec![artist.clone()];
+        }
+        if let Some(album) = &self.album {
+            tags.album = Some(album.clone());
+        }
+    }
+
+    /// Start and end of the part to keep in milliseconds, clamped to the track length.
+    /// Items without a known length (0) aren't cut at the end unless `end` is set.
+    pub fn trim_range(&self, duration_ms: u32) -> (u32, u32) {
+        let length = if duration_ms == 0 {
+            u32::MAX
+        } else {
+            duration_ms
+        };
+        let to_ms = |seconds: f64| ((seconds.max(0.0) * 1000.0) as u32).min(length);
+        let start = self.start.map_or(0, to_ms);
+        let end = self.end.map_or(length, to_ms);
+        (start, end.max(start))
+    }
+}
+
+/// What a track is, written by `save --details` so files can be read without looking up
+/// every URI. It's only there for people, downloads ignore it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TrackInfo {
+    pub artists: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub album: Option<String>,
+    pub duration: String,
+}
+
+#[derive(Serialize)]
 #[serde(untagged)]
 pub(crate) enum SavedTrack {
     Id(String),
-    Object { id: String, name: Option<String> },
+    Object {
+        id: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        #[serde(flatten)]
+        overrides: Box<TrackOverrides>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        info: Option<TrackInfo>,
+        /// Date `refresh` found the track missing from the source
+        #[serde(skip_serializing_if = "Option::is_none")]
+        removed: Option<String>,
+    },
 }
 
 impl SavedTrack {
+    /// Read a track entry, tables can't have unknown keys
+    fn from_value(value: Value) -> Result<Self, String> {
+        let mut table = match value {
+            Value::String(id) => return Ok(SavedTrack::Id(id)),
+            Value::Table(table) => table,
+            other => {
+                return Err(format!(
+                    "expected a URI or a table, found {}",
+                    other.type_str()
+                ));
+            }
+        };
+
+        let id = match table.remove("id") {
+            Some(Value::String(id)) => id,
+            Some(other) => {
+                return Err(format!("`id` must be a string, found {}", other.type_str()));
+            }
+            None => return Err("missing field `id`".to_owned()),
+        };
+        let name = match table.remove("name") {
+            Some(Value::String(name)) => Some(name),
+            Some(other) => {
+                return Err(format!(
+                    "`name` must be a string, found {}",
+                    other.type_str()
+                ));
+            }
+            None => None,
+        };
+        let removed = match table.remove("removed") {
+            Some(Value::String(date)) => Some(date),
+            Some(other) => {
+                return Err(format!(
+                    "`removed` must be a string, found {}",
+                    other.type_str()
+                ));
+            }
+            None => None,
+        };
+        let info = table
+            .remove("info")
+            .map(|info| info.try_into::<TrackInfo>())
+            .transpose()
+            .map_err(|e| format!("in `info`: {}", e.message()))?;
+        let overrides = Value::Table(table)
+            .try_into::<Box<TrackOverrides>>()
+            .map_err(|e| e.message().to_owned())?;
+
+        Ok(SavedTrack::Object {
+            id,
+            name,
+            overrides,
+            info,
+            removed,
+        })
+    }
+
+    /// Make a playlist entry for a track, with its name and info if `details` is set
+    pub async fn fetch(
+        session: &Session,
+        id: SpotifyId,
+        number: u32,
+        details: bool,
+        name_format: Option<&str>,
+    ) -> anyhow::Result<Option<Self>> {
+        let uri = match id.to_uri() {
+            Ok(uri) => uri,
+            Err(err) => {
+                log::error!("Failed to get track URI: {}", err);
+                return Ok(None);
+            }
+        };
+
+        if !details {
+            return Ok(Some(SavedTrack::Id(uri)));
+        }
+
+        let audio_item = AudioItem::get_file(session, id).await?;
+        let name = get_file_name(
+            session,
+            &audio_item,
+            name_format,
+            Some(number),
+            None,
+            &NameStyle::default(),
+        )
+        .await;
+        let (artists, album) = match &audio_item.unique_fields {
+            UniqueFields::Track { artists, album, .. } => (
+                artists.iter().map(|artist| &artist.name).join(", "),
+                Some(album.clone()),
+            ),
+            UniqueFields::Episode { show_name, .. } => (show_name.clone(), None),
+        };
+
+        Ok(Some(SavedTrack::Object {
+            id: uri,
+            name: Some(name),
+            overrides: Box::default(),
+            info: Some(TrackInfo {
+                artists,
+                album,
+                duration: format_duration(audio_item.duration_ms),
+            }),
+            removed: None,
+        }))
+    }
+
+    pub fn uri(&self) -> &str {
+        match self {
+            SavedTrack::Id(id) | SavedTrack::Object { id, .. } => id,
+        }
+    }
+
+    pub fn info(&self) -> Option<&TrackInfo> {
+        match self {
+            SavedTrack::Id(_) => None,
+            SavedTrack::Object { info, .. } => info.as_ref(),
+        }
+    }
+
+    pub fn removed(&self) -> Option<&str> {
+        match self {
+            SavedTrack::Id(_) => None,
+            SavedTrack::Object { removed, .. } => removed.as_deref(),
+        }
+    }
+
+    /// Mark the track as missing from the source, or clear the mark with `None`
+    pub fn set_removed(&mut self, date: Option<String>) {
+        match self {
+            SavedTrack::Id(id) => {
+                if date.is_some() {
+                    *self = SavedTrack::Object {
+                        id: std::mem::take(id),
+                        name: None,
+                        overrides: Box::default(),
+                        info: None,
+                        removed: date,
+                    };
+                }
+            }
+            SavedTrack::Object { removed, .. } => *removed = date,
+        }
+    }
+
     pub fn id(&self) -> Result<SpotifyId, SpotifyError> {
         match self {
             SavedTrack::Id(id) => SpotifyId::from_uri(id),
@@ -22,10 +296,603 @@ impl SavedTrack {
             SavedTrack::Object { name, .. } => name.as_ref().map(|s| s.as_str()),
         }
     }
+
+    pub fn overrides(&self) -> &TrackOverrides {
+        match self {
+            SavedTrack::Id(_) => &NO_OVERRIDES,
+            SavedTrack::Object { overrides, .. } => overrides,
+        }
+    }
+
+    /// Whether the track has a custom name or overrides, `info` and `removed` don't count
+    pub fn is_customized(&self) -> bool {
+        self.name().is_some() || *self.overrides() != NO_OVERRIDES
+    }
+
+    /// Overrides that can be changed, turns a plain URI into a table
+    fn overrides_mut(&mut self) -> &mut TrackOverrides {
+        if let SavedTrack::Id(id) = self {
+            *self = SavedTrack::Object {
+                id: std::mem::take(id),
+                name: None,
+                overrides: Box::default(),
+                info: None,
+                removed: None,
+            };
+        }
+        match self {
+            SavedTrack::Object { overrides, .. } => overrides,
+            SavedTrack::Id(_) => unreachable!(),
+        }
+    }
+
+    /// Whether the entry is an album, playlist or show that's expanded when downloading
+    pub fn is_collection(&self) -> bool {
+        self.id().is_ok_and(|id| {
+            matches!(
+                id.item_type,
+                SpotifyItemType::Album | SpotifyItemType::Playlist | SpotifyItemType::Show
+            )
+        })
+    }
+
+    /// Check things the schema can't, like URIs and trim times
+    fn validate(&self) -> Result<(), String> {
+        let id = match self.id() {
+            Ok(id) => id,
+            Err(e) => return Err(format!("invalid Spotify URI `{}`: {}", self.uri(), e)),
+        };
+        if matches!(
+            id.item_type,
+            SpotifyItemType::Artist | SpotifyItemType::Local | SpotifyItemType::Unknown
+        ) {
+            return Err(format!(
+                "`{}` can't be downloaded, use a track, episode, album, playlist or show",
+                self.uri()
+            ));
+        }
+
+        let overrides = self.overrides();
+        if self.is_collection() {
+            let single_track_only = [
+                ("name", self.name().is_some()),
+                ("title", overrides.title.is_some()),
+                ("start", overrides.start.is_some()),
+                ("end", overrides.end.is_some()),
+            ];
+            if let Some((key, _)) = single_track_only.iter().find(|(_, set)| *set) {
+                return Err(format!(
+                    "`{}` only works for single tracks, not albums, playlists or shows",
+                    key
+                ));
+            }
+        }
+        if overrides.start.is_some_and(|start| start < 0.0) {
+            return Err("`start` can't be negative".to_owned());
+        }
+        if let (Some(start), Some(end)) = (overrides.start, overrides.end)
+            && end <= start
+        {
+            return Err(format!("`end` ({}) must be after `start` ({})", end, start));
+        }
+
+        Ok(())
+    }
 }
 
+/// Tracks are read separately so every bad entry can be reported
 #[derive(Serialize, Deserialize)]
-pub(crate) struct SavedPlaylist {
+#[serde(deny_unknown_fields)]
+pub(crate) struct SavedPlaylist<T = SavedTrack> {
+    #[serde(default = "first_version")]
+    pub version: u32,
     pub title: String,
-    pub tracks: Vec<SavedTrack>,
+    /// Spotify item the playlist was saved from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Defaults used when the command line doesn't set them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<OutputFormat>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name_format: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<PathBuf>,
+    /// Cleanup rules added to the ones from the command line
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cleanup: Vec<String>,
+    /// Other playlist files downloaded along with this one, relative to this file
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<PathBuf>,
+    pub tracks: Vec<T>,
+}
+
+fn first_version() -> u32 {
+    1
+}
+
+impl<T> SavedPlaylist<T> {
+    /// Take the raw track entries out so they can be checked one by one
+    fn split_tracks(self) -> (SavedPlaylist<()>, Vec<T>) {
+        let header = SavedPlaylist {
+            version: self.version,
+            title: self.title,
+            source: self.source,
+            format: self.format,
+            name_format: self.name_format,
+            output: self.output,
+            cleanup: self.cleanup,
+            include: self.include,
+            tracks: vec![],
+        };
+        (header, self.tracks)
+    }
+}
+
+/// Read an M3U file of Spotify URIs or links
+fn parse_m3u_playlist(text: &str) -> Result<SavedPlaylist, Vec<PlaylistError>> {
+    let (title, entries) = parse_m3u(text);
+    let mut tracks = vec![];
+    let mut errors = vec![];
+
+    for entry in entries {
+        match parse_item_ref(&entry.location).and_then(|id| Ok(id.to_uri()?)) {
+            Ok(uri) => tracks.push(SavedTrack::Id(uri)),
+            Err(_) => errors.push(PlaylistError {
+                location: ErrorLocation::Line(entry.line),
+                message: format!(
+                    "`{}` is not a Spotify link or URI, use `daytrip import` for playlists of local files",
+                    entry.location
+                ),
+            }),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(SavedPlaylist::new(title.unwrap_or_default(), tracks))
+}
+
+/// Kinds of playlist files, picked by extension
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum PlaylistFileFormat {
+    Toml,
+    Json,
+    Yaml,
+    /// Spotify URIs or links, one per line. Can be read but not written.
+    M3u,
+}
+
+impl PlaylistFileFormat {
+    /// Format for a file name, files without an extension are TOML
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = match path.extension() {
+            Some(extension) => extension.to_string_lossy().to_lowercase(),
+            None => return Some(PlaylistFileFormat::Toml),
+        };
+        match extension.as_str() {
+            "toml" => Some(PlaylistFileFormat::Toml),
+            "json" => Some(PlaylistFileFormat::Json),
+            "yaml" | "yml" => Some(PlaylistFileFormat::Yaml),
+            "m3u" | "m3u8" => Some(PlaylistFileFormat::M3u),
+            _ => None,
+        }
+    }
+
+    fn for_path(path: &Path) -> anyhow::Result<Self> {
+        match Self::from_path(path) {
+            Some(format) => Ok(format),
+            None => bail!(
+                "Unsupported playlist file {}, use .toml, .json, .yaml or .m3u",
+                path.to_string_lossy()
+            ),
+        }
+    }
+}
+
+/// Where a problem is. JSON and YAML track errors don't have line numbers.
+#[derive(Clone, Copy)]
+pub(crate) enum ErrorLocation {
+    Line(usize),
+    Track(usize),
+}
+
+/// Problem found in a playlist file
+pub(crate) struct PlaylistError {
+    pub location: ErrorLocation,
+    pub message: String,
+}
+
+impl PlaylistError {
+    /// `file:LINE: message` like compilers print it, or `file: track N: message`
+    pub fn in_file(&self, file: &str) -> String {
+        match self.location {
+            ErrorLocation::Line(line) => format!("{}:{}: {}", file, line, self.message),
+            ErrorLocation::Track(number) => format!("{}: track {}: {}", file, number, self.message),
+        }
+    }
+}
+
+fn line_number(text: &str, offset: usize) -> usize {
+    text[..offset.min(text.len())].matches('\n').count() + 1
+}
+
+impl SavedPlaylist {
+    pub fn new(title: String, tracks: Vec<SavedTrack>) -> Self {
+        Self {
+            version: PLAYLIST_VERSION,
+            title,
+            source: None,
+            format: None,
+            name_format: None,
+            output: None,
+            cleanup: vec![],
+            include: vec![],
+            tracks,
+        }
+    }
+
+    /// Parse a TOML playlist file, returns every problem with its line number
+    pub fn parse(text: &str) -> Result<Self, Vec<PlaylistError>> {
+        let raw: SavedPlaylist<Spanned<Value>> = toml::from_str(text).map_err(|e| {
+            vec![PlaylistError {
+                location: ErrorLocation::Line(
+                    e.span().map_or(1, |span| line_number(text, span.start)),
+                ),
+                message: e.message().to_owned(),
+            }]
+        })?;
+
+        let (raw, entries) = raw.split_tracks();
+        let entries = entries
+            .into_iter()
+            .map(|entry| {
+                let line = line_number(text, entry.span().start);
+                (ErrorLocation::Line(line), entry.into_inner())
+            })
+            .collect();
+        Self::from_raw(raw, entries)
+    }
+
+    /// Parse a playlist in any supported format
+    pub fn parse_as(text: &str, format: PlaylistFileFormat) -> Result<Self, Vec<PlaylistError>> {
+        let error = |line: usize, message: String| {
+            vec![PlaylistError {
+                location: ErrorLocation::Line(line),
+                message,
+            }]
+        };
+
+        let raw: SavedPlaylist<Value> = match format {
+            PlaylistFileFormat::Toml => return Self::parse(text),
+            PlaylistFileFormat::M3u => return parse_m3u_playlist(text),
+            PlaylistFileFormat::Json => {
+                serde_json::from_str(text).map_err(|e| error(e.line(), e.to_string()))?
+            }
+            PlaylistFileFormat::Yaml => serde_norway::from_str(text).map_err(|e| {
+                error(
+                    e.location().map_or(1, |location| location.line()),
+                    e.to_string(),
+                )
+            })?,
+        };
+
+        let (raw, entries) = raw.split_tracks();
+        let entries = entries
+            .into_iter()
+            .enumerate()
+            .map(|(idx, entry)| (ErrorLocation::Track(idx + 1), entry))
+            .collect();
+        Self::from_raw(raw, entries)
+    }
+
+    /// Check the header and every track of a parsed file
+    fn from_raw(
+        raw: SavedPlaylist<()>,
+        entries: Vec<(ErrorLocation, Value)>,
+    ) -> Result<Self, Vec<PlaylistError>> {
+        let mut errors = vec![];
+        if raw.version > PLAYLIST_VERSION {
+            errors.push(PlaylistError {
+                location: ErrorLocation::Line(1),
+                message: format!(
+                    "version {} is newer than this daytrip supports ({})",
+                    raw.version, PLAYLIST_VERSION
+                ),
+            });
+        }
+
+        let mut tracks = vec![];
+        for (location, entry) in entries {
+            match SavedTrack::from_value(entry).and_then(|track| {
+                track.validate()?;
+                Ok(track)
+            }) {
+                Ok(track) => tracks.push(track),
+                Err(message) => errors.push(PlaylistError { location, message }),
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(SavedPlaylist {
+            version: raw.version,
+            title: raw.title,
+            source: raw.source,
+            format: raw.format,
+            name_format: raw.name_format,
+            output: raw.output,
+            cleanup: raw.cleanup,
+            include: raw.include,
+            tracks,
+        })
+    }
+
+    /// Write the playlist with the current version, in the format of the file extension
+    pub fn save(&mut self, path: &Path) -> anyhow::Result<()> {
+        self.version = PLAYLIST_VERSION;
+        let text = match PlaylistFileFormat::for_path(path)? {
+            PlaylistFileFormat::Toml => toml::to_string_pretty(self)?,
+            PlaylistFileFormat::Json => serde_json::to_string_pretty(self)? + "\n",
+            PlaylistFileFormat::Yaml => serde_norway::to_string(self)?,
+            PlaylistFileFormat::M3u => bail!(
+                "Can't write {}, M3U files can't hold names and overrides. Use a .toml, .json or .yaml file instead",
+                path.to_string_lossy()
+            ),
+        };
+        std::fs::write(path, text)
+            .with_context(|| format!("Failed to write {}", path.to_string_lossy()))
+    }
+
+    /// Read and validate a playlist file
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let format = PlaylistFileFormat::for_path(path)?;
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                bail!("Playlist file not found: {}", path.to_string_lossy())
+            }
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to read {}", path.to_string_lossy()));
+            }
+        };
+
+        match Self::parse_as(&text, format) {
+            Ok(mut playlist) => {
+                // M3U files don't always have a title
+                if playlist.title.is_empty() {
+                    playlist.title = path
+                        .file_stem()
+                        .map(|stem| stem.to_string_lossy().into_owned())
+                        .unwrap_or_default();
+                }
+                Ok(playlist)
+            }
+            Err(errors) => {
+                for error in &errors {
+                    eprintln!("{}", error.in_file(&path.to_string_lossy()));
+                }
+                bail!(
+                    "{} has {} error(s), see `daytrip check`",
+                    path.to_string_lossy(),
+                    errors.len()
+                );
+            }
+        }
+    }
+}
+
+impl SavedPlaylist {
+    /// Add the tracks of included files, so the result can be downloaded in one go. Each
+    /// included file goes into a folder named after its `output` or title, and its `format`
+    /// is used for tracks that don't set their own when the command line doesn't either. File names and cleanup rules apply to the
+    /// whole download, so included files can't set `name_format` or `cleanup`.
+    pub fn with_includes(mut self, path: &Path) -> anyhow::Result<Self> {
+        let mut parents = vec![path.canonicalize()?];
+        self.add_includes(path, &mut parents)?;
+        Ok(self)
+    }
+
+    fn add_includes(&mut self, path: &Path, parents: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+        let base = path.parent().unwrap_or(Path::new(""));
+
+        for include in std::mem::take(&mut self.include) {
+            let include_path = base.join(&include);
+            let canonical = include_path.canonicalize().with_context(|| {
+                format!(
+                    "Playlist file not found: {}, included from {}",
+                    include_path.to_string_lossy(),
+                    path.to_string_lossy()
+                )
+            })?;
+            if parents.contains(&canonical) {
+                bail!(
+                    "{} includes itself through {}",
+                    include_path.to_string_lossy(),
+                    path.to_string_lossy()
+                );
+            }
+
+            let mut included = SavedPlaylist::load(&include_path)?;
+            if included.name_format.is_some() || !included.cleanup.is_empty() {
+                bail!(
+                    "{} is included from {}, so it can't set `name_format` or `cleanup`",
+                    include_path.to_string_lossy(),
+                    path.to_string_lossy()
+                );
+            }
+            parents.push(canonical);
+            included.add_includes(&include_path, parents)?;
+            parents.pop();
+
+            let folder = included
+                .output
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(&included.title));
+            for mut track in included.tracks {
+                let overrides = track.overrides_mut();
+                overrides.folder = Some(match overrides.folder.take() {
+                    Some(subfolder) => folder.join(subfolder),
+                    None => folder.clone(),
+                });
+                overrides.included_format = overrides.included_format.or(included.format);
+                self.tracks.push(track);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Point `include` and `output`, which are relative to the file, at the same places
+    /// when the playlist is saved at `to` instead of `from`
+    pub fn move_paths(&mut self, from: &Path, to: &Path) -> anyhow::Result<()> {
+        let from = parent_dir(from);
+        let to = parent_dir(to);
+        for include in &mut self.include {
+            *include = rebase_path(include, from, to)?;
+        }
+        if let Some(output) = &mut self.output {
+            *output = rebase_path(output, from, to)?;
+        }
+        Ok(())
+    }
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    path.parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+}
+
+/// Rewrite a path relative to `from` so it's relative to `to`
+fn rebase_path(path: &Path, from: &Path, to: &Path) -> anyhow::Result<PathBuf> {
+    if path.is_absolute() {
+        return Ok(path.to_owned());
+    }
+    let target = clean_path(&std::path::absolute(from.join(path))?);
+    let base = clean_path(&std::path::absolute(to)?);
+
+    // Other drives on Windows can't be reached with `..`
+    if target.components().next() != base.components().next() {
+        return Ok(target);
+    }
+    let common = target
+        .components()
+        .zip(base.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut rebased = base
+        .components()
+        .skip(common)
+        .map(|_| Component::ParentDir)
+        .collect::<PathBuf>();
+    rebased.extend(target.components().skip(common));
+
+    if rebased.as_os_str().is_empty() {
+        rebased.push(".");
+    }
+    Ok(rebased)
+}
+
+/// Resolve `.` and `..` without touching the file system
+fn clean_path(path: &Path) -> PathBuf {
+    let mut clean = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                clean.pop();
+            }
+            other => clean.push(other),
+        }
+    }
+    clean
+}
+
+/// Bring an older playlist file up to the current version. Older versions are a subset of
+/// the current one, so this only adds the version and keeps comments and formatting.
+fn migrate(text: &str, version: u32) -> String {
+    match version {
+        1 => format!("version = {}\n{}", PLAYLIST_VERSION, text),
+        _ => VERSION_LINE
+            .replace(text, format!("version = {}", PLAYLIST_VERSION))
+            .into_owned(),
+    }
+}
+
+/// Name and track IDs of a Spotify item that can be saved as a playlist
+pub(crate) async fn fetch_source(
+    session: &Session,
+    item_ref: SpotifyId,
+) -> anyhow::Result<(String, Vec<SpotifyId>)> {
+    Ok(match item_ref.item_type {
+        SpotifyItemType::Album => {
+            let album = Album::get(session, &item_ref).await?;
+            let ids = album.tracks().copied().collect();
+            (album.name, ids)
+        }
+        SpotifyItemType::Playlist => {
+            let plist = Playlist::get(session, &item_ref).await?;
+            (plist.name().to_owned(), plist.tracks().copied().collect())
+        }
+        SpotifyItemType::Show => {
+            let show = Show::get(session, &item_ref).await?;
+            let ids = show.episodes.iter().copied().collect();
+            (show.name, ids)
+        }
+        SpotifyItemType::Track | SpotifyItemType::Episode => {
+            let audio_item = AudioItem::get_file(session, item_ref).await?;
+            (audio_item.name, vec![item_ref])
+        }
+        _ => bail!("Unsupported item type: {:?}", item_ref.item_type),
+    })
+}
+
+pub(crate) fn check(cmd: CheckArgs) -> anyhow::Result<()> {
+    let format = PlaylistFileFormat::for_path(&cmd.file)?;
+    let text = std::fs::read_to_string(&cmd.file)
+        .with_context(|| format!("Failed to read {}", cmd.file.to_string_lossy()))?;
+    let file_name = cmd.file.to_string_lossy();
+
+    let playlist = match SavedPlaylist::parse_as(&text, format) {
+        Ok(playlist) => playlist,
+        Err(errors) => {
+            for error in &errors {
+                println!("{}", error.in_file(&file_name));
+            }
+            bail!("Found {} error(s) in {}", errors.len(), file_name);
+        }
+    };
+
+    println!("{} is valid, {} tracks", file_name, playlist.tracks.len());
+    if !playlist.include.is_empty() {
+        let tracks = playlist.tracks.len();
+        let expanded = SavedPlaylist::load(&cmd.file)?.with_includes(&cmd.file)?;
+        println!(
+            "Included files are valid, {} more tracks",
+            expanded.tracks.len() - tracks
+        );
+    }
+
+    // Only TOML files can be updated without losing comments
+    if playlist.version < PLAYLIST_VERSION && format == PlaylistFileFormat::Toml {
+        if cmd.migrate {
+            std::fs::write(&cmd.file, migrate(&text, playlist.version))
+                .context("Failed to write migrated playlist")?;
+            println!(
+                "Migrated from version {} to {}",
+                playlist.version, PLAYLIST_VERSION
+            );
+        } else {
+            println!(
+                "This file uses version {}, run with --migrate to update it to {}",
+                playlist.version, PLAYLIST_VERSION
+            );
+        }
+    }
+
+    Ok(())
 }
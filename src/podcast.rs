@@ -0,0 +1,257 @@
+use std::path::Path;
+
+use anyhow::Context;
+use librespot::metadata::audio::{AudioItem, UniqueFields};
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+
+use crate::tags::format_date;
+
+/// Matches HTML tags, group 1 is the slash of a closing tag, 2 is the name and 3 the attributes
+static HTML_TAG: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"<(/?)([a-zA-Z][a-zA-Z0-9]*)([^>]*)>").unwrap());
+static HTML_ENTITY: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"&(#[xX][0-9a-fA-F]+|#[0-9]+|[a-zA-Z]+);").unwrap());
+static HREF: Lazy<Regex> = Lazy::new(|| Regex::new(r#"href\s*=\s*["']([^"']*)["']"#).unwrap());
+/// A line starting with a timestamp like `12:34` or `(1:02:03)`, followed by the chapter title
+static CHAPTER_LINE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^[\s\-*]*[(\[]?(?:(\d{1,2}):)?(\d{1,2}):(\d{2})[)\]]?\s*[-–—:|.]?\s*(.+)$")
+        .unwrap()
+});
+
+/// Format of the show notes file saved next to each episode
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq)]
+pub enum ShowNotesMode {
+    /// Don't save show notes
+    #[default]
+    None,
+    /// Plain text
+    Txt,
+    /// Markdown with links
+    Md,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Chapter {
+    pub start_ms: u32,
+    pub end_ms: u32,
+    pub title: String,
+}
+
+/// Convert an HTML episode description to readable text, or to Markdown if `markdown` is set.
+/// Descriptions without any tags only get their entities decoded.
+pub(crate) fn html_to_text(html: &str, markdown: bool) -> String {
+    if !HTML_TAG.is_match(html) {
+        return decode_entities(html).trim().to_owned();
+    }
+
+    let mut text = String::new();
+    // Start of the current link text and where it points
+    let mut link: Option<(usize, String)> = None;
+    let mut last = 0;
+
+    for tag in HTML_TAG.captures_iter(html) {
+        let whole = tag.get(0).unwrap();
+        push_text(&mut text, &html[last..whole.start()]);
+        last = whole.end();
+
+        let closing = !tag[1].is_empty();
+        match (tag[2].to_ascii_lowercase().as_str(), closing) {
+            ("br", _) => text.push('\n'),
+            ("p" | "div" | "ul" | "ol" | "h1" | "h2" | "h3" | "h4", _) => text.push_str("\n\n"),
+            ("li", false) => text.push_str("\n- "),
+            ("b" | "strong", _) if markdown => text.push_str("**"),
+            ("i" | "em", _) if markdown => text.push('*'),
+            ("a", false) => {
+                let href = HREF
+                    .captures(&tag[3])
+                    .map(|href| decode_entities(&href[1]))
+                    .unwrap_or_default();
+                if markdown {
+                    text.push('[');
+                }
+                link = Some((text.len(), href));
+            }
+            ("a", true) => {
+                if let Some((start, href)) = link.take() {
+                    if markdown {
+                        text.push_str(&format!("]({})", href));
+                    } else if !href.is_empty() && text[start..].trim() != href {
+                        text.push_str(&format!(" ({})", href));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    push_text(&mut text, &html[last..]);
+
+    // Tidy up the whitespace left around block tags
+    let mut clean = String::new();
+    let mut blank_lines = 0;
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() {
+            blank_lines += 1;
+            continue;
+        }
+        if !clean.is_empty() {
+            clean.push_str(if blank_lines > 0 { "\n\n" } else { "\n" });
+        }
+        clean.push_str(line);
+        blank_lines = 0;
+    }
+
+    clean
+}
+
+/// Add text between HTML tags, whitespace collapses like it would in a browser
+fn push_text(text: &mut String, segment: &str) {
+    let words = segment.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    if segment.starts_with(char::is_whitespace) && !text.ends_with(char::is_whitespace) {
+        text.push(' ');
+    }
+    text.push_str(&decode_entities(&words));
+    if !words.is_empty() && segment.ends_with(char::is_whitespace) {
+        text.push(' ');
+    }
+}
+
+fn decode_entities(text: &str) -> String {
+    HTML_ENTITY
+        .replace_all(text, |entity: &Captures| {
+            let name = &entity[1];
+            let decoded = match name {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                _ if name.starts_with("#x") || name.starts_with("#X") => {
+                    u32::from_str_radix(&name[2..], 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                }
+                _ if name.starts_with('#') => name[1..].parse().ok().and_then(char::from_u32),
+                _ => None,
+            };
+            decoded.map_or_else(|| entity[0].to_owned(), String::from)
+        })
+        .into_owned()
+}
+
+/// Find chapters in a description with one timestamped line per chapter. Returns nothing
+/// unless there are at least two timestamps in order and inside the episode.
+pub(crate) fn parse_chapters(description: &str, duration_ms: u32) -> Vec<Chapter> {
+    let mut chapters: Vec<Chapter> = vec![];
+
+    for line in description.lines() {
+        let Some(chapter) = CHAPTER_LINE.captures(line) else {
+            continue;
+        };
+        let part = |i: usize| {
+            chapter
+                .get(i)
+                .map_or(0, |m| m.as_str().parse().unwrap_or(0))
+        };
+        let start_ms = (part(1) * 3600 + part(2) * 60 + part(3)) * 1000;
+
+        if start_ms >= duration_ms
+            || chapters
+                .last()
+                .is_some_and(|last| last.start_ms >= start_ms)
+        {
+            return vec![];
+        }
+        if let Some(last) = chapters.last_mut() {
+            last.end_ms = start_ms;
+        }
+        chapters.push(Chapter {
+            start_ms,
+            end_ms: duration_ms,
+            title: chapter[4].trim().to_owned(),
+        });
+    }
+
+    if chapters.len() < 2 {
+        return vec![];
+    }
+    chapters
+}
+
+/// Chapter start time for Vorbis comments
+pub(crate) fn format_chapter_time(ms: u32) -> String {
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+/// Write chapters in ffmpeg's metadata format so they can be muxed into the container
+pub(crate) async fn write_ffmetadata(path: &Path, chapters: &[Chapter]) -> anyhow::Result<()> {
+    let escape = |value: &str| {
+        value
+            .chars()
+            .flat_map(|c| match c {
+                '=' | ';' | '#' | '\\' | '\n' => vec!['\\', c],
+                c => vec![c],
+            })
+            .collect::<String>()
+    };
+
+    let mut metadata = ";FFMETADATA1\n".to_owned();
+    for chapter in chapters {
+        metadata += &format!(
+            "[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+            chapter.start_ms,
+            chapter.end_ms,
+            escape(&chapter.title)
+        );
+    }
+
+    tokio::fs::write(path, metadata)
+        .await
+        .context("Failed to write chapters file")
+}
+
+/// Save the episode description next to the audio file
+pub(crate) async fn write_show_notes(
+    audio_path: &Path,
+    audio_item: &AudioItem,
+    mode: ShowNotesMode,
+) -> anyhow::Result<()> {
+    let UniqueFields::Episode {
+        description,
+        publish_time,
+        show_name,
+    } = &audio_item.unique_fields
+    else {
+        return Ok(());
+    };
+
+    let markdown = mode == ShowNotesMode::Md;
+    let (extension, heading) = if markdown {
+        ("md", format!("# {}", audio_item.name))
+    } else {
+        ("txt", audio_item.name.clone())
+    };
+    let byline = match format_date(publish_time.as_timestamp_ms()) {
+        Some(date) => format!("{} · {}", show_name, date),
+        None => show_name.clone(),
+    };
+
+    let notes = format!(
+        "{}\n\n{}\n\n{}\n",
+        heading,
+        byline,
+        html_to_text(description, markdown)
+    );
+
+    tokio::fs::write(audio_path.with_extension(extension), notes)
+        .await
+        .context("Failed to write show notes")
+}
@@ -0,0 +1,88 @@
+use std::collections::HashSet;
+
+use anyhow::{anyhow, bail};
+use chrono::Local;
+use librespot::core::SpotifyId;
+
+use crate::{
+    RefreshArgs,
+    core::Loader,
+    playlist::{SavedPlaylist, SavedTrack, fetch_source},
+};
+
+/// Update a saved playlist from the Spotify item it was saved from. New tracks are added
+/// at the end and missing ones are marked, everything else in the file stays as it is.
+pub(crate) async fn refresh(loader: &Loader, cmd: RefreshArgs) -> anyhow::Result<()> {
+    let session = loader.get_session();
+    let mut plist = SavedPlaylist::load(&cmd.file)?;
+
+    let Some(source) = plist.source.clone() else {
+        bail!(
+            "{} doesn't have a source, save it again with `daytrip save` to refresh it",
+            cmd.file.to_string_lossy()
+        );
+    };
+    let source_ref = SpotifyId::from_uri(&source)
+        .map_err(|e| anyhow!("Invalid source URI {}: {}", source, e))?;
+
+    let (_, ids) = fetch_source(session, source_ref).await?;
+    let current = ids
+        .iter()
+        .filter_map(|id| id.to_uri().ok())
+        .collect::<HashSet<_>>();
+
+    let today = Local::now().date_naive().to_string();
+    let mut removed = 0;
+    // Albums, playlists and shows added by hand aren't part of the source
+    for track in plist
+        .tracks
+        .iter_mut()
+        .filter(|track| !track.is_collection())
+    {
+        let in_source = current.contains(track.uri());
+        if !in_source && track.removed().is_none() {
+            println!("Removed {}", track.name().unwrap_or(track.uri()));
+            track.set_removed(Some(today.clone()));
+            removed += 1;
+        } else if in_source && track.removed().is_some() {
+            println!("Back again {}", track.name().unwrap_or(track.uri()));
+            track.set_removed(None);
+        }
+    }
+
+    let mut known = plist
+        .tracks
+        .iter()
+        .map(|track| track.uri().to_owned())
+        .collect::<HashSet<_>>();
+    let mut added = 0;
+    for (idx, id) in ids.iter().enumerate() {
+        if id.to_uri().is_ok_and(|uri| known.contains(&uri)) {
+            continue;
+        }
+        let Some(track) = SavedTrack::fetch(
+            session,
+            *id,
+            idx as u32 + 1,
+            cmd.details_args.details,
+            cmd.details_args.name_format.as_deref(),
+        )
+        .await?
+        else {
+            continue;
+        };
+
+        println!("Added {}", track.name().unwrap_or(track.uri()));
+        known.insert(track.uri().to_owned());
+        plist.tracks.push(track);
+        added += 1;
+    }
+
+    println!("{} new, {} removed", added, removed);
+
+    if cmd.dry_run {
+        return Ok(());
+    }
+
+    plist.save(&cmd.file)
+}
@@ -0,0 +1,252 @@
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, bail};
+use librespot::{
+    core::{Session, SpotifyId},
+    metadata::{
+        Album, Metadata, Track,
+        audio::{AudioItem, UniqueFields},
+    },
+};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::{
+    RenameArgs,
+    core::{Loader, OutputFormat},
+    metadata::get_file_name,
+    normalize::NameStyle,
+    tags::{ArtistTags, Tags, read_uri, write_tags},
+};
+
+/// File written into the renamed folder so the last rename can be reverted
+const UNDO_LOG: &str = "daytrip-rename.toml";
+/// Files saved next to the audio file with the same name, renamed along with it
+const SIDECAR_EXTENSIONS: &[&str] = &["lrc", "txt", "md"];
+
+#[derive(Serialize, Deserialize)]
+struct RenameEntry {
+    from: PathBuf,
+    to: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct UndoLog {
+    renames: Vec<RenameEntry>,
+}
+
+/// List audio files daytrip can create in a folder
+pub(crate) async fn list_audio_files(folder: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut entries = fs::read_dir(folder)
+        .await
+        .with_context(|| format!("Failed to open folder {}", folder.to_string_lossy()))?;
+
+    let mut files = vec![];
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(OutputFormat::from_extension)
+            .is_some()
+        {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    Ok(files)
+}
+
+/// Look up the Spotify item a downloaded file came from
+pub(crate) async fn find_audio_item(
+    session: &Session,
+    path: &Path,
+) -> anyhow::Result<Option<AudioItem>> {
+    let Some(uri) = read_uri(path)? else {
+        log::warn!("No Spotify URI in {}, skipping", path.to_string_lossy());
+        return Ok(None);
+    };
+    let id = match SpotifyId::from_uri(&uri) {
+        Ok(id) => id,
+        Err(e) => {
+            log::warn!(
+                "Invalid Spotify URI {} in {}: {}",
+                uri,
+                path.to_string_lossy(),
+                e
+            );
+            return Ok(None);
+        }
+    };
+
+    Ok(Some(AudioItem::get_file(session, id).await?))
+}
+
+pub(crate) async fn rename(loader: &Loader, cmd: RenameArgs) -> anyhow::Result<()> {
+    if cmd.undo {
+        return undo(&cmd.folder, cmd.dry_run).await;
+    }
+
+    let mut log = UndoLog::default();
+    let mut albums = HashMap::new();
+
+    for path in list_audio_files(&cmd.folder).await? {
+        let Some(audio_item) = find_audio_item(loader.get_session(), &path).await? else {
+            continue;
+        };
+
+        let track_number = match &audio_item.unique_fields {
+            UniqueFields::Track { number, .. } => {
+                match album_position(loader.get_session(), &audio_item.track_id, &mut albums).await
+                {
+                    Ok(position) => Some(position.unwrap_or(*number)),
+                    Err(e) => {
+                        log::warn!("Failed to get album of {}: {}", audio_item.name, e);
+                        Some(*number)
+                    }
+                }
+            }
+            UniqueFields::Episode { .. } => None,
+        };
+        let extension = path.extension().and_then(|ext| ext.to_str());
+        let format = extension
+            .and_then(OutputFormat::from_extension)
+            .unwrap_or_default();
+        let name = get_file_name(
+            loader.get_session(),
+            &audio_item,
+            cmd.name_format.as_deref(),
+            track_number,
+            extension,
+            &NameStyle::default(),
+        )
+        .await;
+        let new_path = cmd.folder.join(&name);
+
+        if cmd.retag && !cmd.dry_run {
+            let mut tags = Tags::fetch(loader.get_session(), &audio_item).await;
+            tags.keep_from_file(&path)?;
+            write_tags(&path, format, &tags, &ArtistTags::default())?;
+        }
+
+        if new_path == path {
+            continue;
+        }
+        if new_path.exists() {
+            log::warn!(
+                "{} already exists, not renaming {}",
+                new_path.to_string_lossy(),
+                path.to_string_lossy()
+            );
+            continue;
+        }
+
+        println!(
+            "{} -> {}",
+            path.to_string_lossy(),
+            new_path.to_string_lossy()
+        );
+
+        if !cmd.dry_run {
+            if let Some(parent) = new_path.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            fs::rename(&path, &new_path).await?;
+            log.renames.push(RenameEntry {
+                from: path.clone(),
+                to: new_path.clone(),
+            });
+        }
+
+        for extension in SIDECAR_EXTENSIONS {
+            let sidecar = path.with_extension(extension);
+            let new_sidecar = new_path.with_extension(extension);
+            if !sidecar.exists() {
+                continue;
+            }
+            if new_sidecar.exists() {
+                log::warn!(
+                    "{} already exists, not renaming {}",
+                    new_sidecar.to_string_lossy(),
+                    sidecar.to_string_lossy()
+                );
+                continue;
+            }
+
+            println!(
+                "{} -> {}",
+                sidecar.to_string_lossy(),
+                new_sidecar.to_string_lossy()
+            );
+
+            if !cmd.dry_run {
+                fs::rename(&sidecar, &new_sidecar).await?;
+                log.renames.push(RenameEntry {
+                    from: sidecar,
+                    to: new_sidecar,
+                });
+            }
+        }
+    }
+
+    if !cmd.dry_run && !log.renames.is_empty() {
+        fs::write(cmd.folder.join(UNDO_LOG), toml::to_string_pretty(&log)?)
+            .await
+            .context("Failed to write undo log")?;
+    }
+
+    Ok(())
+}
+
+/// Position of a track on its album counting all discs, the number an album download
+/// gives it. Album track lists are kept since a folder is usually one album.
+async fn album_position(
+    session: &Session,
+    track_id: &SpotifyId,
+    albums: &mut HashMap<SpotifyId, Vec<SpotifyId>>,
+) -> anyhow::Result<Option<u32>> {
+    let track = Track::get(session, track_id).await?;
+    let album_id = track.album.id;
+
+    let tracks = match albums.entry(album_id) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => {
+            let album = Album::get(session, &album_id).await?;
+            entry.insert(album.tracks().copied().collect())
+        }
+    };
+
+    Ok(tracks
+        .iter()
+        .position(|id| id == track_id)
+        .map(|idx| idx as u32 + 1))
+}
+
+async fn undo(folder: &Path, dry_run: bool) -> anyhow::Result<()> {
+    let log_path = folder.join(UNDO_LOG);
+    let Ok(buf) = fs::read_to_string(&log_path).await else {
+        bail!("No undo log found in {}", folder.to_string_lossy());
+    };
+    let log: UndoLog = toml::from_str(&buf)?;
+
+    for entry in log.renames.iter().rev() {
+        println!(
+            "{} -> {}",
+            entry.to.to_string_lossy(),
+            entry.from.to_string_lossy()
+        );
+        if !dry_run {
+            fs::rename(&entry.to, &entry.from).await?;
+        }
+    }
+
+    if !dry_run {
+        fs::remove_file(&log_path).await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,31 @@
+use anyhow::Context;
+
+use crate::{
+    RetagArgs,
+    core::{Loader, OutputFormat},
+    rename::{find_audio_item, list_audio_files},
+    tags::{Tags, write_tags},
+};
+
+pub(crate) async fn retag(loader: &Loader, cmd: RetagArgs) -> anyhow::Result<()> {
+    for path in list_audio_files(&cmd.folder).await? {
+        let Some(audio_item) = find_audio_item(loader.get_session(), &path).await? else {
+            continue;
+        };
+
+        let format = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(OutputFormat::from_extension)
+            .unwrap_or_default();
+
+        println!("Tagging {}", path.to_string_lossy());
+
+        let mut tags = Tags::fetch(loader.get_session(), &audio_item).await;
+        tags.keep_from_file(&path)?;
+        write_tags(&path, format, &tags, &cmd.artist_tags)
+            .with_context(|| format!("Failed to tag {}", path.to_string_lossy()))?;
+    }
+
+    Ok(())
+}
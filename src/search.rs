@@ -0,0 +1,307 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use bytes::Bytes;
+use clap::Parser;
+use http::{Request, header::AUTHORIZATION};
+use itertools::Itertools;
+use librespot::core::Session;
+use serde::Deserialize;
+
+use crate::{
+    DownloadArgs, SearchArgs,
+    core::Loader,
+    download,
+    metadata::format_duration,
+    playlist::{SavedPlaylist, SavedTrack},
+};
+
+const WEB_API: &str = "https://api.spotify.com/v1";
+
+/// A track from the Web API search
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct FoundTrack {
+    pub uri: String,
+    pub name: String,
+    pub artists: Vec<FoundArtist>,
+    pub album: FoundAlbum,
+    pub duration_ms: u32,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct FoundArtist {
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct FoundAlbum {
+    pub name: String,
+}
+
+impl FoundTrack {
+    pub fn artist_names(&self) -> String {
+        join_artists(&self.artists)
+    }
+}
+
+fn join_artists(artists: &[FoundArtist]) -> String {
+    artists.iter().map(|artist| &artist.name).join(", ")
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct FoundAlbumItem {
+    uri: String,
+    name: String,
+    artists: Vec<FoundArtist>,
+    #[serde(default)]
+    release_date: String,
+    total_tracks: u32,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct FoundArtistItem {
+    uri: String,
+    name: String,
+    #[serde(default)]
+    genres: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct FoundShow {
+    uri: String,
+    name: String,
+    publisher: String,
+    total_episodes: u32,
+}
+
+/// Item types `daytrip search` can look for
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum SearchType {
+    Track,
+    Album,
+    Artist,
+    Show,
+}
+
+impl SearchType {
+    fn name(&self) -> &str {
+        match self {
+            SearchType::Track => "track",
+            SearchType::Album => "album",
+            SearchType::Artist => "artist",
+            SearchType::Show => "show",
+        }
+    }
+}
+
+/// One search result of any type
+pub(crate) struct SearchResult {
+    pub uri: String,
+    pub name: String,
+    /// Artists, album, publisher and so on
+    pub details: String,
+}
+
+// The Web API sometimes returns `null` in place of items it can't show
+#[derive(Deserialize)]
+struct Page<T> {
+    items: Vec<Option<T>>,
+}
+
+#[derive(Deserialize)]
+struct SearchResponse {
+    tracks: Option<Page<FoundTrack>>,
+    albums: Option<Page<FoundAlbumItem>>,
+    artists: Option<Page<FoundArtistItem>>,
+    shows: Option<Page<FoundShow>>,
+}
+
+fn items<T>(page: Option<Page<T>>) -> impl Iterator<Item = T> {
+    page.into_iter().flat_map(|page| page.items).flatten()
+}
+
+/// GET a Web API endpoint with the session token
+async fn web_api_get(session: &Session, path: &str) -> anyhow::Result<Bytes> {
+    let token = session
+        .token_provider()
+        .get_token("user-read-private")
+        .await?;
+    let request = Request::get(format!("{}/{}", WEB_API, path))
+        .header(AUTHORIZATION, format!("Bearer {}", token.access_token))
+        .body(Bytes::new())?;
+
+    Ok(session.http_client().request_body(request).await?)
+}
+
+async fn search_raw(
+    session: &Session,
+    query: &str,
+    search_type: SearchType,
+    limit: u32,
+) -> anyhow::Result<SearchResponse> {
+    let body = web_api_get(
+        session,
+        &format!(
+            "search?{}",
+            form_urlencoded::Serializer::new(String::new())
+                .append_pair("type", search_type.name())
+                .append_pair("limit", &limit.to_string())
+                .append_pair("q", query)
+                .finish()
+        ),
+    )
+    .await?;
+
+    serde_json::from_slice(&body).context("Unexpected search response")
+}
+
+/// Search Spotify for tracks, best matches first
+pub(crate) async fn search_tracks(
+    session: &Session,
+    query: &str,
+    limit: u32,
+) -> anyhow::Result<Vec<FoundTrack>> {
+    let response = search_raw(session, query, SearchType::Track, limit).await?;
+    Ok(items(response.tracks).collect())
+}
+
+/// Search Spotify for any item type, best matches first
+pub(crate) async fn search(
+    session: &Session,
+    query: &str,
+    search_type: SearchType,
+    limit: u32,
+) -> anyhow::Result<Vec<SearchResult>> {
+    let response = search_raw(session, query, search_type, limit).await?;
+
+    let tracks = items(response.tracks).map(|track| SearchResult {
+        details: format!(
+            "{} · {} · {}",
+            track.artist_names(),
+            track.album.name,
+            format_duration(track.duration_ms)
+        ),
+        uri: track.uri,
+        name: track.name,
+    });
+    let albums = items(response.albums).map(|album| {
+        let year = album.release_date.split('-').next().unwrap_or_default();
+        SearchResult {
+            details: format!(
+                "{} · {} · {} tracks",
+                join_artists(&album.artists),
+                year,
+                album.total_tracks
+            ),
+            uri: album.uri,
+            name: album.name,
+        }
+    });
+    let artists = items(response.artists).map(|artist| SearchResult {
+        details: artist.genres.join(", "),
+        uri: artist.uri,
+        name: artist.name,
+    });
+    let shows = items(response.shows).map(|show| SearchResult {
+        details: format!("{} · {} episodes", show.publisher, show.total_episodes),
+        uri: show.uri,
+        name: show.name,
+    });
+
+    Ok(tracks.chain(albums).chain(artists).chain(shows).collect())
+}
+
+/// Print search results and, with `--interactive`, download or save the one picked
+pub(crate) async fn run(loader: &Loader, cmd: SearchArgs) -> anyhow::Result<()> {
+    let session = loader.get_session();
+    let results = search(session, &cmd.query, cmd.search_type, cmd.limit).await?;
+
+    if results.is_empty() {
+        println!("Nothing found for {}", cmd.query);
+        return Ok(());
+    }
+
+    for (idx, result) in results.iter().enumerate() {
+        println!("{:>2}. {}", idx + 1, result.name);
+        if !result.details.is_empty() {
+            println!("    {}", result.details);
+        }
+        println!("    {}", result.uri);
+    }
+
+    if !cmd.interactive {
+        return Ok(());
+    }
+
+    let picked = loop {
+        let answer = prompt("Pick a result, or press enter to quit: ")?;
+        if answer.is_empty() {
+            return Ok(());
+        }
+        match answer.parse::<usize>() {
+            Ok(number) if (1..=results.len()).contains(&number) => break &results[number - 1],
+            _ => println!("Enter a number from 1 to {}", results.len()),
+        }
+    };
+
+    if cmd.search_type == SearchType::Artist {
+        println!(
+            "Artists can't be downloaded, search for their albums with `--type album` instead"
+        );
+        return Ok(());
+    }
+
+    let action = prompt("[d]ownload or [a]dd to a TOML playlist? ")?;
+    match action.to_lowercase().as_str() {
+        "d" | "download" => {
+            let mut args = vec!["get".to_owned(), picked.uri.clone()];
+            if let Some(output) = &cmd.output {
+                args.push(output.to_string_lossy().into_owned());
+            }
+            download(loader, DownloadArgs::try_parse_from(args)?).await
+        }
+        "a" | "add" => {
+            let path = match cmd.playlist {
+                Some(path) => path,
+                None => PathBuf::from(prompt("Playlist file: ")?),
+            };
+            add_to_playlist(&path, &picked.uri)
+        }
+        _ => Ok(()),
+    }
+}
+
+fn prompt(message: &str) -> anyhow::Result<String> {
+    print!("{}", message);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim().to_owned())
+}
+
+/// Add a track, album or show to a playlist file, it's created if it doesn't exist
+fn add_to_playlist(path: &Path, uri: &str) -> anyhow::Result<()> {
+    let mut plist = if path.exists() {
+        SavedPlaylist::load(path)?
+    } else {
+        let title = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        SavedPlaylist::new(title, vec![])
+    };
+
+    if plist.tracks.iter().any(|track| track.uri() == uri) {
+        println!("{} is already in {}", uri, path.to_string_lossy());
+        return Ok(());
+    }
+
+    plist.tracks.push(SavedTrack::Id(uri.to_owned()));
+    plist.save(path)?;
+    println!("Added {} to {}", uri, path.to_string_lossy());
+
+    Ok(())
+}
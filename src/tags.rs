@@ -0,0 +1,608 @@
+use std::path::Path;
+
+use chrono::DateTime;
+use librespot::{
+    core::{Session, SpotifyId},
+    metadata::{
+        Album, Artist, Metadata, Track,
+        audio::{AudioItem, UniqueFields},
+    },
+};
+
+use lofty::{
+    config::WriteOptions,
+    id3::v2::{BinaryFrame, Frame, FrameId, Id3v2Tag},
+    picture::{MimeType, Picture, PictureType},
+    prelude::*,
+    tag::{ItemValue, Tag, TagItem, TagType},
+};
+
+use crate::{
+    cleanup::{CleanupField, clean_tag},
+    core::OutputFormat,
+    loudness::{ReplayGain, format_gain, format_peak, parse_gain, parse_r128_gain, r128_gain},
+    podcast::{Chapter, format_chapter_time, html_to_text, parse_chapters},
+};
+
+/// How items with more than one artist are tagged
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq)]
+pub enum ArtistTagMode {
+    /// One value per artist
+    #[default]
+    Split,
+    /// A single joined string
+    Joined,
+    /// Joined string in ARTIST and one value per artist in ARTISTS
+    Both,
+}
+
+#[derive(clap::Parser, Clone, Debug)]
+pub(crate) struct ArtistTags {
+    /// How to tag items with more than one artist
+    #[arg(
+        long = "artist-tags",
+        value_name = "ARTIST_TAGS",
+        value_enum,
+        default_value_t = ArtistTagMode::Split
+    )]
+    pub mode: ArtistTagMode,
+
+    /// Separator for the joined artist string used by `joined` and `both`
+    /// artist tags, and by WAV files
+    #[arg(
+        long = "artist-separator",
+        value_name = "ARTIST_SEPARATOR",
+        default_value = ", "
+    )]
+    pub separator: String,
+}
+
+impl ArtistTags {
+    /// Artists as one display string
+    pub fn join(&self, artists: &[String]) -> String {
+        artists.join(&self.separator)
+    }
+}
+
+impl Default for ArtistTags {
+    fn default() -> Self {
+        Self {
+            mode: ArtistTagMode::default(),
+            separator: ", ".to_owned(),
+        }
+    }
+}
+
+/// Everything we know about an item that can go into its tags
+#[derive(Default, Debug, Clone)]
+pub(crate) struct Tags {
+    pub title: String,
+    pub artists: Vec<String>,
+    pub album: Option<String>,
+    pub album_artists: Vec<String>,
+    pub track_number: Option<u32>,
+    pub track_total: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub disc_total: Option<u32>,
+    /// Release date as `YYYY-MM-DD`
+    pub date: Option<String>,
+    pub isrc: Option<String>,
+    pub label: Option<String>,
+    pub copyright: Option<String>,
+    pub genres: Vec<String>,
+    pub explicit: bool,
+    pub uri: String,
+    pub spotify_track_id: Option<String>,
+    pub spotify_album_id: Option<String>,
+    pub spotify_episode_id: Option<String>,
+    pub show: Option<String>,
+    /// Episode description as plain text
+    pub description: Option<String>,
+    pub chapters: Vec<Chapter>,
+    /// Front cover as JPEG
+    pub cover: Option<Vec<u8>>,
+    pub lyrics: Option<String>,
+    pub replay_gain: Option<ReplayGain>,
+    /// Samples per channel in the encoded audio, used for gapless playback
+    pub sample_count: Option<u64>,
+}
+
+pub(crate) fn format_date(timestamp_ms: i64) -> Option<String> {
+    DateTime::from_timestamp_millis(timestamp_ms).map(|date| date.format("%Y-%m-%d").to_string())
+}
+
+impl Tags {
+    /// Collect tags for an audio item. Track, album and artist metadata is fetched
+    /// separately, if that fails we fall back to what the audio item has.
+    pub async fn fetch(session: &Session, audio_item: &AudioItem) -> Self {
+        let mut tags = Tags {
+            title: clean_tag(CleanupField::Title, &audio_item.name),
+            explicit: audio_item.is_explicit,
+            uri: audio_item.uri.clone(),
+            ..Default::default()
+        };
+
+        match &audio_item.unique_fields {
+            UniqueFields::Episode {
+                show_name,
+                description,
+                publish_time,
+            } => {
+                tags.show = Some(clean_tag(CleanupField::Artist, show_name));
+                let description = html_to_text(description, false);
+                tags.chapters = parse_chapters(&description, audio_item.duration_ms);
+                tags.description = Some(description);
+                tags.date = format_date(publish_time.as_timestamp_ms());
+                tags.spotify_episode_id = audio_item.track_id.to_base62().ok();
+            }
+            UniqueFields::Track {
+                artists,
+                album,
+                album_artists,
+                number,
+                disc_number,
+                ..
+            } => {
+                tags.artists = artists
+                    .iter()
+                    .map(|artist| clean_tag(CleanupField::Artist, &artist.name))
+                    .collect();
+                tags.album = Some(album.clone());
+                tags.album_artists = album_artists
+                    .iter()
+                    .map(|artist| clean_tag(CleanupField::Artist, artist))
+                    .collect();
+                tags.track_number = Some(*number);
+                tags.disc_number = Some(*disc_number);
+                tags.spotify_track_id = audio_item.track_id.to_base62().ok();
+
+                if let Err(e) = tags
+                    .fetch_track_details(session, &audio_item.track_id)
+                    .await
+                {
+                    log::warn!(
+                        "Failed to get extra metadata for {}: {}",
+                        audio_item.name,
+                        e
+                    );
+                }
+            }
+        }
+
+        tags
+    }
+
+    async fn fetch_track_details(
+        &mut self,
+        session: &Session,
+        track_id: &SpotifyId,
+    ) -> anyhow::Result<()> {
+        let track = Track::get(session, track_id).await?;
+
+        self.isrc = track
+            .external_ids
+            .iter()
+            .find(|external_id| external_id.external_type.eq_ignore_ascii_case("isrc"))
+            .map(|external_id| external_id.id.clone());
+        self.explicit = track.is_explicit;
+
+        let album = Album::get(session, &track.album.id).await?;
+
+        self.spotify_album_id = album.id.to_base62().ok();
+        self.date = format_date(album.date.as_timestamp_ms());
+        self.label = Some(album.label.clone()).filter(|label| !label.is_empty());
+        self.copyright = album.copyrights.first().map(|c| c.text.clone());
+        self.disc_total = Some(album.discs.len() as u32);
+        self.track_total = album
+            .discs
+            .iter()
+            .find(|disc| disc.number == track.disc_number)
+            .map(|disc| disc.tracks.len() as u32);
+        self.genres = album.genres.clone();
+
+        if let Some(image) = album.covers.iter().max_by_key(|image| image.width) {
+            match session.spclient().get_image(&image.id).await {
+                Ok(data) => self.cover = Some(data.to_vec()),
+                Err(e) => log::warn!("Failed to get cover for {}: {}", album.name, e),
+            }
+        }
+
+        // Albums rarely have genres, the main artist usually does
+        if self.genres.is_empty()
+            && let Some(artist) = track.artists.first()
+        {
+            let artist = Artist::get(session, &artist.id).await?;
+            self.genres = artist.genre.clone();
+        }
+
+        Ok(())
+    }
+
+    /// Keep what an encoded file has that can't be fetched from Spotify again, so
+    /// retagging doesn't lose it
+    pub fn keep_from_file(&mut self, path: &Path) -> anyhow::Result<()> {
+        let tagged_file = lofty::read_from_path(path)?;
+        let Some(tag) = tagged_file.primary_tag() else {
+            return Ok(());
+        };
+
+        // Only M4A keeps gapless info in a tag, MP3 has it in the audio frames
+        self.sample_count = self.sample_count.or_else(|| {
+            tag.get_string(&itunes_key("iTunSMPB"))
+                .and_then(parse_itunes_smpb)
+        });
+        self.replay_gain = self.replay_gain.or_else(|| read_replay_gain(tag));
+        if self.lyrics.is_none() {
+            self.lyrics = tag.get_string(&ItemKey::Lyrics).map(str::to_owned);
+        }
+
+        Ok(())
+    }
+}
+
+fn tag_type(format: OutputFormat) -> TagType {
+    match format {
+        OutputFormat::Opus | OutputFormat::Ogg => TagType::VorbisComments,
+        OutputFormat::Mp3 => TagType::Id3v2,
+        OutputFormat::Wav => TagType::RiffInfo,
+        OutputFormat::M4a => TagType::Mp4Ilst,
+    }
+}
+
+/// Read the Spotify URI daytrip saves in the comment tag
+pub(crate) fn read_uri(path: &Path) -> anyhow::Result<Option<String>> {
+    let tagged_file = lofty::read_from_path(path)?;
+
+    Ok(tagged_file
+        .primary_tag()
+        .and_then(|tag| tag.get_string(&ItemKey::Comment))
+        .filter(|comment| comment.starts_with("spotify:"))
+        .map(str::to_owned))
+}
+
+/// Replace all tags in an encoded file
+pub(crate) fn write_tags(
+    path: &Path,
+    format: OutputFormat,
+    tags: &Tags,
+    settings: &ArtistTags,
+) -> anyhow::Result<()> {
+    let mut tag = Tag::new(tag_type(format));
+
+    tag.insert_text(ItemKey::TrackTitle, tags.title.clone());
+    tag.insert_text(ItemKey::Comment, tags.uri.clone());
+
+    // Podcast players look for the show in the album and artist fields
+    if let Some(show) = &tags.show {
+        tag.insert_text(ItemKey::AlbumTitle, show.clone());
+        tag.insert_text(ItemKey::TrackArtist, show.clone());
+    }
+    if let Some(description) = &tags.description {
+        tag.insert_text(ItemKey::Description, description.clone());
+    }
+
+    match settings.mode {
+        ArtistTagMode::Split => {
+            set_multi_value(
+                &mut tag,
+                format,
+                ItemKey::TrackArtist,
+                &tags.artists,
+                settings,
+            );
+            set_multi_value(
+                &mut tag,
+                format,
+                ItemKey::AlbumArtist,
+                &tags.album_artists,
+                settings,
+            );
+        }
+        ArtistTagMode::Joined => {
+            set_joined(&mut tag, ItemKey::TrackArtist, &tags.artists, settings);
+            set_joined(
+                &mut tag,
+                ItemKey::AlbumArtist,
+                &tags.album_artists,
+                settings,
+            );
+        }
+        ArtistTagMode::Both => {
+            set_joined(&mut tag, ItemKey::TrackArtist, &tags.artists, settings);
+            set_joined(
+                &mut tag,
+                ItemKey::AlbumArtist,
+                &tags.album_artists,
+                settings,
+            );
+            set_multi_value(
+                &mut tag,
+                format,
+                ItemKey::TrackArtists,
+                &tags.artists,
+                settings,
+            );
+        }
+    }
+
+    if let Some(album) = &tags.album {
+        tag.insert_text(ItemKey::AlbumTitle, album.clone());
+    }
+    if let Some(number) = tags.track_number {
+        tag.insert_text(ItemKey::TrackNumber, number.to_string());
+    }
+    if let Some(total) = tags.track_total {
+        tag.insert_text(ItemKey::TrackTotal, total.to_string());
+    }
+    if let Some(number) = tags.disc_number {
+        tag.insert_text(ItemKey::DiscNumber, number.to_string());
+    }
+    if let Some(total) = tags.disc_total {
+        tag.insert_text(ItemKey::DiscTotal, total.to_string());
+    }
+    if let Some(date) = &tags.date {
+        tag.insert_text(ItemKey::RecordingDate, date.clone());
+    }
+    if let Some(isrc) = &tags.isrc {
+        tag.insert_text(ItemKey::Isrc, isrc.clone());
+    }
+    if let Some(label) = &tags.label {
+        tag.insert_text(ItemKey::Label, label.clone());
+    }
+    if let Some(copyright) = &tags.copyright {
+        tag.insert_text(ItemKey::CopyrightMessage, copyright.clone());
+    }
+    set_multi_value(&mut tag, format, ItemKey::Genre, &tags.genres, settings);
+    tag.insert_text(
+        ItemKey::ParentalAdvisory,
+        if tags.explicit { "1" } else { "0" }.to_owned(),
+    );
+
+    if let Some(gain) = &tags.replay_gain {
+        write_replay_gain(&mut tag, format, gain);
+    }
+
+    if let Some(lyrics) = &tags.lyrics {
+        tag.insert_text(ItemKey::Lyrics, lyrics.clone());
+    }
+
+    if let Some(cover) = &tags.cover {
+        tag.push_picture(Picture::new_unchecked(
+            PictureType::CoverFront,
+            Some(MimeType::Jpeg),
+            None,
+            cover.clone(),
+        ));
+    }
+
+    let custom_fields = [
+        ("SPOTIFY_TRACK_ID", &tags.spotify_track_id),
+        ("SPOTIFY_ALBUM_ID", &tags.spotify_album_id),
+        ("SPOTIFY_EPISODE_ID", &tags.spotify_episode_id),
+    ];
+
+    match format {
+        // ID3v2 needs TXXX frames for custom fields, the generic tag can't make those
+        OutputFormat::Mp3 => {
+            let mut tag: Id3v2Tag = tag.into();
+            for (name, value) in custom_fields {
+                if let Some(value) = value {
+                    tag.insert_user_text(name.to_owned(), value.clone());
+                }
+            }
+            insert_id3_chapters(&mut tag, &tags.chapters)?;
+            tag.save_to_path(path, WriteOptions::default())?;
+        }
+        // RIFF INFO only has a handful of fields, the rest are dropped
+        OutputFormat::Wav => {
+            tag.save_to_path(path, WriteOptions::default())?;
+        }
+        OutputFormat::Opus | OutputFormat::Ogg => {
+            for (name, value) in custom_fields {
+                if let Some(value) = value {
+                    tag.insert_text(ItemKey::Unknown(name.to_owned()), value.clone());
+                }
+            }
+            // Chapter extension for Vorbis comments, CHAPTER001=00:00:00.000 and CHAPTER001NAME
+            for (i, chapter) in tags.chapters.iter().enumerate() {
+                let key = format!("CHAPTER{:03}", i + 1);
+                tag.insert_text(
+                    ItemKey::Unknown(format!("{}NAME", key)),
+                    chapter.title.clone(),
+                );
+                tag.insert_text(ItemKey::Unknown(key), format_chapter_time(chapter.start_ms));
+            }
+            tag.save_to_path(path, WriteOptions::default())?;
+        }
+        // Custom fields are freeform iTunes atoms. Chapters are muxed by ffmpeg, the
+        // tag only replaces the metadata atoms so they are kept.
+        OutputFormat::M4a => {
+            for (name, value) in custom_fields {
+                if let Some(value) = value {
+                    tag.insert_text(itunes_key(name), value.clone());
+                }
+            }
+            if let Some(sample_count) = tags.sample_count {
+                tag.insert_text(itunes_key("iTunSMPB"), itunes_smpb(sample_count));
+            }
+            tag.save_to_path(path, WriteOptions::default())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Add ID3v2 CHAP frames and a table of contents so players can jump between chapters
+fn insert_id3_chapters(tag: &mut Id3v2Tag, chapters: &[Chapter]) -> anyhow::Result<()> {
+    if chapters.is_empty() {
+        return Ok(());
+    }
+
+    let mut toc = b"toc\0".to_vec();
+    // Top level and ordered
+    toc.push(0b11);
+    toc.push(chapters.len().min(u8::MAX as usize) as u8);
+
+    for (i, chapter) in chapters.iter().take(u8::MAX as usize).enumerate() {
+        let id = format!("chp{}", i + 1);
+        toc.extend(id.as_bytes());
+        toc.push(0);
+
+        let mut data = id.into_bytes();
+        data.push(0);
+        data.extend(chapter.start_ms.to_be_bytes());
+        data.extend(chapter.end_ms.to_be_bytes());
+        // Byte offsets are unused
+        data.extend(u32::MAX.to_be_bytes());
+        data.extend(u32::MAX.to_be_bytes());
+        data.extend(id3_text_frame("TIT2", &chapter.title));
+
+        tag.insert(Frame::Binary(BinaryFrame::new(FrameId::new("CHAP")?, data)));
+    }
+    tag.insert(Frame::Binary(BinaryFrame::new(FrameId::new("CTOC")?, toc)));
+
+    Ok(())
+}
+
+/// Encode a UTF-8 ID3v2.4 text frame, used as a sub-frame of chapters
+fn id3_text_frame(id: &str, text: &str) -> Vec<u8> {
+    let size = text.len() as u32 + 1;
+    // Frame sizes are synchsafe integers, 7 bits per byte
+    let synchsafe = [
+        (size >> 21 & 0x7F) as u8,
+        (size >> 14 & 0x7F) as u8,
+        (size >> 7 & 0x7F) as u8,
+        (size & 0x7F) as u8,
+    ];
+
+    let mut frame = id.as_bytes().to_vec();
+    frame.extend(synchsafe);
+    frame.extend([0, 0, 3]);
+    frame.extend(text.as_bytes());
+    frame
+}
+
+fn itunes_key(name: &str) -> ItemKey {
+    ItemKey::Unknown(format!("----:com.apple.iTunes:{}", name))
+}
+
+/// Gapless info for AAC in the format iTunes uses. ffmpeg's AAC encoder adds one
+/// frame of priming samples and pads the end to a whole frame.
+fn itunes_smpb(sample_count: u64) -> String {
+    const AAC_FRAME_SIZE: u64 = 1024;
+    const ENCODER_DELAY: u64 = AAC_FRAME_SIZE;
+
+    let frames = (sample_count + ENCODER_DELAY).div_ceil(AAC_FRAME_SIZE);
+    let padding = frames * AAC_FRAME_SIZE - ENCODER_DELAY - sample_count;
+
+    format!(
+        " 00000000 {:08X} {:08X} {:016X} 00000000 00000000 00000000 00000000 00000000 00000000 00000000 00000000",
+        ENCODER_DELAY, padding, sample_count
+    )
+}
+
+/// Sample count from an iTunSMPB value written by [`itunes_smpb`]
+fn parse_itunes_smpb(value: &str) -> Option<u64> {
+    let sample_count = value.split_whitespace().nth(3)?;
+    u64::from_str_radix(sample_count, 16).ok()
+}
+
+/// ReplayGain values in a tag written by [`write_replay_gain`]
+fn read_replay_gain(tag: &Tag) -> Option<ReplayGain> {
+    let get = |key: &str| tag.get_string(&ItemKey::Unknown(key.to_owned()));
+
+    // Opus files only have R128 gains, the peaks aren't written so they're left at full scale
+    if let Some(track_gain_db) = get("R128_TRACK_GAIN").and_then(parse_r128_gain) {
+        return Some(ReplayGain {
+            track_gain_db,
+            track_peak: 1.0,
+            album_gain: get("R128_ALBUM_GAIN")
+                .and_then(parse_r128_gain)
+                .map(|gain| (gain, 1.0)),
+        });
+    }
+
+    let track_gain_db = tag
+        .get_string(&ItemKey::ReplayGainTrackGain)
+        .and_then(parse_gain)?;
+    let peak = |key| {
+        tag.get_string(&key)
+            .and_then(|peak| peak.trim().parse().ok())
+    };
+    Some(ReplayGain {
+        track_gain_db,
+        track_peak: peak(ItemKey::ReplayGainTrackPeak).unwrap_or(1.0),
+        album_gain: tag
+            .get_string(&ItemKey::ReplayGainAlbumGain)
+            .and_then(parse_gain)
+            .map(|gain| (gain, peak(ItemKey::ReplayGainAlbumPeak).unwrap_or(1.0))),
+    })
+}
+
+fn write_replay_gain(tag: &mut Tag, format: OutputFormat, gain: &ReplayGain) {
+    match format {
+        // Opus players ignore REPLAYGAIN_* and use R128 tags without peaks
+        OutputFormat::Opus => {
+            tag.insert_text(
+                ItemKey::Unknown("R128_TRACK_GAIN".to_owned()),
+                r128_gain(gain.track_gain_db),
+            );
+            if let Some((album_gain_db, _)) = gain.album_gain {
+                tag.insert_text(
+                    ItemKey::Unknown("R128_ALBUM_GAIN".to_owned()),
+                    r128_gain(album_gain_db),
+                );
+            }
+        }
+        OutputFormat::Ogg | OutputFormat::Mp3 | OutputFormat::M4a => {
+            tag.insert_text(
+                ItemKey::ReplayGainTrackGain,
+                format_gain(gain.track_gain_db),
+            );
+            tag.insert_text(ItemKey::ReplayGainTrackPeak, format_peak(gain.track_peak));
+            if let Some((album_gain_db, album_peak)) = gain.album_gain {
+                tag.insert_text(ItemKey::ReplayGainAlbumGain, format_gain(album_gain_db));
+                tag.insert_text(ItemKey::ReplayGainAlbumPeak, format_peak(album_peak));
+            }
+        }
+        // RIFF INFO has no ReplayGain fields
+        OutputFormat::Wav => {}
+    }
+}
+
+fn set_joined(tag: &mut Tag, key: ItemKey, values: &[String], settings: &ArtistTags) {
+    if !values.is_empty() {
+        tag.insert_text(key, settings.join(values));
+    }
+}
+
+fn set_multi_value(
+    tag: &mut Tag,
+    format: OutputFormat,
+    key: ItemKey,
+    values: &[String],
+    settings: &ArtistTags,
+) {
+    if values.is_empty() {
+        return;
+    }
+
+    tag.remove_key(&key);
+
+    match format {
+        // ID3v2.4 separates values in a single text frame with nulls
+        OutputFormat::Mp3 => {
+            tag.insert_text(key, values.join("\0"));
+        }
+        // RIFF INFO can only hold one value
+        OutputFormat::Wav => {
+            tag.insert_text(key, settings.join(values));
+        }
+        // Vorbis comments and MP4 atoms can be repeated
+        OutputFormat::Opus | OutputFormat::Ogg | OutputFormat::M4a => {
+            for value in values {
+                tag.push(TagItem::new(key.clone(), ItemValue::Text(value.clone())));
+            }
+        }
+    }
+}